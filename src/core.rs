@@ -1,9 +1,21 @@
 pub mod actions;
 mod array2d;
 pub mod canvas;
+pub mod command;
+pub mod command_line;
+pub mod cp437;
 pub mod gallery;
+pub mod history;
+pub mod input;
+pub mod keymap;
+pub mod layers;
+pub mod macros;
+pub mod registers;
+pub mod repeat;
+pub mod shapes;
 
 use euclid::default::Point2D;
+use shapes::BoxGlyphs;
 
 pub type CharID = u16;
 
@@ -17,6 +29,11 @@ pub trait Charset: Clone {
 
     /// Returns the number of characters in this set
     fn len(&self) -> u16;
+
+    /// The characters to draw box outlines with, if the set has them
+    fn box_glyphs(&self) -> Option<BoxGlyphs> {
+        None
+    }
 }
 
 pub type CanvasPos = Point2D<u16>;
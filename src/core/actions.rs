@@ -1,36 +1,468 @@
+use std::{iter::Peekable, ops::Range};
+
 use euclid::default::Vector2D;
-use logos::Logos;
+use logos::{Logos, SpannedIter};
+use thiserror::Error;
+
+use super::command::{Command, CommandError};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+enum LexError {
+    #[default]
+    UnknownKey,
+    CountOverflow,
+}
 
-#[derive(Logos, Debug)]
+#[derive(Logos, Debug, Clone, Copy, PartialEq, Eq)]
+#[logos(error = LexError)]
 enum Token {
-    #[regex(r"[0-9]+", |lex| lex.slice().parse::<i32>().ok())]
+    #[regex(r"[0-9]+", |lex| lex.slice().parse::<i32>().map_err(|_| LexError::CountOverflow))]
     Mult(i32),
 
     #[token("h")]
+    #[token("<Left>")]
     Left,
 
     #[token("l")]
+    #[token("<Right>")]
     Right,
 
     #[token("k")]
+    #[token("<Up>")]
     Up,
 
     #[token("j")]
+    #[token("<Down>")]
     Down,
+
+    #[token("g")]
+    Go,
+
+    #[token("G")]
+    LastRow,
+
+    #[token("d")]
+    Delete,
+
+    #[token("r")]
+    Recolor,
+
+    #[token("y")]
+    Yank,
+
+    #[token("p")]
+    PutAfter,
+
+    #[token("P")]
+    PutBefore,
+
+    #[regex("\"[a-z]", |lex| lex.slice().chars().nth(1))]
+    Register(char),
+
+    /// Starts naming a register
+    #[token("\"")]
+    Quote,
+
+    #[token("u")]
+    Undo,
+
+    #[token("<C-r>")]
+    Redo,
+
+    #[token(" ")]
+    Paint,
+
+    #[token("<Tab>")]
+    NextFrame,
+
+    /// Starts a window command
+    #[token("<C-w>")]
+    Window,
+
+    #[token("w")]
+    WindowNext,
+
+    #[token("W")]
+    WindowPrevious,
+
+    #[token("v")]
+    #[token("<C-v>")]
+    Visual,
+
+    /// Starts a flood fill, followed by what cells have to share to be filled
+    #[token("f")]
+    #[token("F")]
+    Bucket,
+
+    /// Starts drawing a shape over the selection, followed by which shape
+    #[token("s")]
+    #[token("S")]
+    Shape,
+
+    #[token(":")]
+    Colon,
+
+    #[token(".")]
+    Repeat,
+
+    /// Starts recording a macro, followed by the register to record into
+    #[token("q")]
+    Record,
+
+    /// Plays a macro, followed by its register or `@` for the last one played
+    #[token("@")]
+    Play,
+}
+
+/// The names keys can be bound to in a keymap, and the key each one stands for
+pub const BINDABLE: &[(&str, &str)] = &[
+    ("left", "h"),
+    ("right", "l"),
+    ("up", "k"),
+    ("down", "j"),
+    ("go", "g"),
+    ("last-row", "G"),
+    ("delete", "d"),
+    ("recolor", "r"),
+    ("yank", "y"),
+    ("put-after", "p"),
+    ("put-before", "P"),
+    ("register", "\""),
+    ("undo", "u"),
+    ("redo", "<C-r>"),
+    ("paint", " "),
+    ("next-frame", "<Tab>"),
+    ("window", "<C-w>"),
+    ("window-next", "w"),
+    ("window-previous", "W"),
+    ("visual", "v"),
+    ("fill", "f"),
+    ("fill-diagonal", "F"),
+    ("shape", "s"),
+    ("box-shape", "S"),
+    ("command-line", ":"),
+    ("repeat", "."),
+    ("record", "q"),
+    ("play", "@"),
+    ("cancel", "<Esc>"),
+];
+
+/// Whether the next key after a buffer is read as typed rather than as an
+/// action, like the register after `"` or what a flood fill matches by. Those
+/// keys aren't remapped.
+pub fn takes_argument(buffer: &str) -> bool {
+    matches!(
+        Token::lexer(buffer).last(),
+        Some(Ok(Token::Quote
+            | Token::Bucket
+            | Token::Shape
+            | Token::Record
+            | Token::Play))
+    )
 }
 
 impl Token {
     fn is_direction(&self) -> bool {
         matches!(self, Self::Left | Self::Right | Self::Up | Self::Down)
     }
+
+    fn is_motion(&self) -> bool {
+        self.is_direction() || matches!(self, Self::Go | Self::LastRow)
+    }
+
+    fn operator(&self) -> Option<Operator> {
+        match self {
+            Self::Delete => Some(Operator::Delete),
+            Self::Yank => Some(Operator::Yank),
+            _ => None,
+        }
+    }
+
+    /// Operators that only make sense on a selection, where they act straight away
+    fn visual_operator(&self) -> Option<Operator> {
+        match self {
+            Self::Paint => Some(Operator::Fill),
+            Self::Recolor => Some(Operator::Recolor),
+            tok => tok.operator(),
+        }
+    }
+}
+
+/// What keys mean depends on whether something is selected
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    #[default]
+    Normal,
+    /// A block is being selected, motions grow it and operators act on it
+    Visual,
+}
+
+impl Mode {
+    /// What the status bar says when there's nothing else to say, like vim's
+    /// `-- VISUAL --recording @a`
+    pub fn status(self, recording: Option<char>) -> String {
+        let mode = match self {
+            Mode::Normal => "",
+            Mode::Visual => "-- VISUAL --",
+        };
+        match recording {
+            Some(register) => format!("{mode}recording @{register}"),
+            None => mode.into(),
+        }
+    }
 }
 
+/// A row of a canvas that can be jumped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Row {
+    First,
+    Last,
+    /// Zero based index of a row
+    Nth(u16),
+}
+
+/// The region an operator acts upon, relative to the cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    /// Moving by an offset, e.g. `3l`
+    By(Vector2D<i32>),
+    /// Jumping to a row, e.g. `gg` or `5G`
+    ToRow(Row),
+    /// The cursor's row and the ones below it, e.g. `dd` or `3dd`
+    Rows(u16),
+    /// The block between the cursor and where `v` was pressed
+    Selection,
+}
+
+impl Motion {
+    /// Whether the motion covers whole rows rather than the cells along one
+    pub fn is_linewise(&self) -> bool {
+        match self {
+            Self::By(v) => v.y != 0,
+            Self::Selection => false,
+            Self::ToRow(_) | Self::Rows(_) => true,
+        }
+    }
+}
+
+/// Which neighbours of a cell a flood fill spreads to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Only the cells beside, above and below
+    Four,
+    /// Diagonals as well
+    Eight,
+}
+
+impl Connectivity {
+    pub fn offsets(&self) -> &'static [(i32, i32)] {
+        match self {
+            Self::Four => &[(1, 0), (-1, 0), (0, 1), (0, -1)],
+            Self::Eight => &[
+                (1, 0),
+                (-1, 0),
+                (0, 1),
+                (0, -1),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ],
+        }
+    }
+}
+
+/// What a cell must share with the one a flood fill starts on to be filled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillMatch {
+    Glyph,
+    Foreground,
+    Background,
+    /// The glyph and both colours
+    Cell,
+}
+
+/// Something drawn between the cursor and where a selection started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    Line,
+    Rectangle { filled: bool },
+    Ellipse { filled: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// Resets cells back to their defaults, keeping a copy in a register
+    Delete,
+    /// Copies cells into a register
+    Yank,
+    /// Stamps the brush onto every cell
+    Fill,
+    /// Gives cells the brush's colours, keeping their characters
+    Recolor,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
     MoveCursor(Vector2D<i32>),
+    JumpToRow(Row),
+    Operate(Operator, Motion),
+    /// Reverts this many edits
+    Undo(u16),
+    /// Reapplies this many undone edits
+    Redo(u16),
+    /// Stamps the brush onto this many cells, from the cursor rightwards
+    Paint(u16),
+    /// Moves focus this many frames forwards, or backwards when negative
+    CycleFocus(i32),
+    /// Makes the last change again at the cursor, with a new count if one is given
+    Repeat(Option<u16>),
+    /// Opens the `:` command line, which keys go to until it's entered
+    CommandLine,
+    /// Starts recording the keys typed into a register, until `q` is pressed
+    Record(char),
+    /// Types the keys recorded in a register this many times, or the last
+    /// register played when `None`
+    PlayMacro {
+        register: Option<char>,
+        count: u16,
+    },
+    /// A command typed on the command line
+    Command(Command),
+    /// Throws away the keys typed so far, from pressing escape. Also stops selecting.
+    Cancel,
+    /// Starts selecting a block from the cursor, or stops if already selecting
+    Visual,
+    /// Names the register that the next yank, delete or put uses
+    Register(char),
+    /// Stamps a register's block this many times side by side, starting at the
+    /// cursor or the cell after it
+    Put {
+        count: u16,
+        before: bool,
+        /// Leaves cells alone where the block has the blank character
+        skip_blank: bool,
+    },
+    /// Stamps the brush over the cells connected to the cursor that match it
+    FloodFill {
+        connectivity: Connectivity,
+        by: FillMatch,
+    },
+    /// Draws a shape with the brush, ending the selection
+    Draw {
+        shape: Shape,
+        /// Uses the charset's line characters for edges and corners instead
+        /// of the brush's glyph
+        box_drawing: bool,
+    },
 }
 
+impl Action {
+    /// Whether the action changes cells, which makes it what `.` repeats
+    pub fn is_change(&self) -> bool {
+        matches!(
+            self,
+            Self::Operate(Operator::Delete | Operator::Fill | Operator::Recolor, _)
+                | Self::Paint(_)
+                | Self::Put { .. }
+                | Self::FloodFill { .. }
+                | Self::Draw { .. }
+        )
+    }
+
+    /// Whether the action stamps the brush, which comes from outside the canvas
+    pub fn needs_brush(&self) -> bool {
+        matches!(
+            self,
+            Self::Operate(Operator::Fill | Operator::Recolor, _)
+                | Self::Paint(_)
+                | Self::FloodFill { .. }
+                | Self::Draw { .. }
+        )
+    }
+
+    /// The same action with a different count, like vim's `3.`. Actions on a
+    /// selection, and ones without a count, stay as they are.
+    pub fn with_count(self, count: u16) -> Self {
+        match self {
+            Self::Paint(_) => Self::Paint(count),
+            Self::Put {
+                before, skip_blank, ..
+            } => Self::Put {
+                count,
+                before,
+                skip_blank,
+            },
+            Self::Operate(operator, motion) => {
+                let motion = match motion {
+                    Motion::By(v) => Motion::By(v.map(|d| d.signum() * count as i32)),
+                    Motion::Rows(_) => Motion::Rows(count),
+                    Motion::ToRow(_) => Motion::ToRow(Row::Nth(count.saturating_sub(1))),
+                    Motion::Selection => Motion::Selection,
+                };
+                Self::Operate(operator, motion)
+            }
+            action => action,
+        }
+    }
+}
+
+/// Why a buffer of keys can never become a valid action. Every variant holds the
+/// whole buffer along with the span of the keys at fault, so that the buffer can
+/// be reported and then cleared.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum ActionError {
+    #[error("unknown command `{buffer}`")]
+    UnknownKey { buffer: String, span: Range<usize> },
 
+    #[error("count is too large in `{buffer}`")]
+    CountOverflow { buffer: String, span: Range<usize> },
+
+    #[error("count does not apply to anything in `{buffer}`")]
+    DanglingCount { buffer: String, span: Range<usize> },
+
+    #[error("{operator:?} cannot be used with `{}`", &buffer[span.clone()])]
+    InvalidPairing {
+        operator: Operator,
+        buffer: String,
+        span: Range<usize>,
+    },
+
+    #[error("{error}")]
+    BadCommand {
+        error: CommandError,
+        buffer: String,
+        span: Range<usize>,
+    },
+
+    #[error("`{buffer}` plays too many keys, does the macro play itself?")]
+    RunawayMacro { buffer: String, span: Range<usize> },
+}
+
+impl ActionError {
+    /// The entire buffer of keys that caused the error
+    pub fn buffer(&self) -> &str {
+        match self {
+            Self::UnknownKey { buffer, .. }
+            | Self::CountOverflow { buffer, .. }
+            | Self::DanglingCount { buffer, .. }
+            | Self::InvalidPairing { buffer, .. }
+            | Self::BadCommand { buffer, .. }
+            | Self::RunawayMacro { buffer, .. } => buffer,
+        }
+    }
+
+    /// Where in the buffer the offending keys are
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Self::UnknownKey { span, .. }
+            | Self::CountOverflow { span, .. }
+            | Self::DanglingCount { span, .. }
+            | Self::InvalidPairing { span, .. }
+            | Self::BadCommand { span, .. }
+            | Self::RunawayMacro { span, .. } => span.clone(),
+        }
+    }
 }
 
 fn direction_to_vector(tok: Token, count: i32) -> Option<Vector2D<i32>> {
@@ -44,24 +476,669 @@ fn direction_to_vector(tok: Token, count: i32) -> Option<Vector2D<i32>> {
     .into()
 }
 
-pub fn parse_move_cursor(input: &str) -> Option<Action> {
-    let mut lex = Token::lexer(input);
+/// Why the parser could not produce an action (yet)
+enum Halt {
+    /// Ran out of keys, more input could still complete the action
+    Incomplete,
+    Failed(ActionError),
+}
 
-    let count = match lex.next() {
-        Some(Ok(Token::Mult(count))) => count,
-        Some(Ok(tok)) if tok.is_direction() => {
-            return direction_to_vector(tok, 1).map(Action::MoveCursor)
+type Parsed<T> = Result<T, Halt>;
+
+/// Recursive descent over the tokens of an action buffer.
+///
+/// Grammar, where every count is optional:
+/// ```text
+/// action   := register | ":" | record | count (motion | history | paint | focus | visual | put
+///                                              | fill | "." | play
+///                                              | operator count (operator | motion))
+/// register := '"' ("a" .. "z")
+/// record   := "q" ("a" .. "z")
+/// play     := "@" ("a" .. "z" | "@")
+/// put      := "g"? ("p" | "P")
+/// fill     := ("f" | "F") ("g" | "f" | "b" | "c")
+/// history  := "u" | <C-r>
+/// paint    := <Space>
+/// focus    := <Tab> | <C-w> (<C-w> | "w" | "W")
+/// visual   := "v" | <C-v>
+/// motion   := direction | "gg" | "G"
+/// ```
+///
+/// In visual mode `"d"`, `<Space>` and `"r"` operate on the selection without a motion,
+/// and shapes can be drawn over it:
+/// ```text
+/// shape    := ("s" | "S") ("l" | "r" | "R" | "e" | "E")
+/// ```
+struct ActionParser<'a> {
+    buffer: &'a str,
+    tokens: Peekable<SpannedIter<'a, Token>>,
+    mode: Mode,
+}
+
+impl<'a> ActionParser<'a> {
+    fn new(buffer: &'a str, mode: Mode) -> Self {
+        Self {
+            buffer,
+            tokens: Token::lexer(buffer).spanned().peekable(),
+            mode,
         }
-        _ => return None,
-    };
+    }
+
+    fn fail<T>(
+        &self,
+        make: fn(String, Range<usize>) -> ActionError,
+        span: Range<usize>,
+    ) -> Parsed<T> {
+        Err(Halt::Failed(make(self.buffer.into(), span)))
+    }
+
+    fn next(&mut self) -> Parsed<(Token, Range<usize>)> {
+        match self.tokens.next() {
+            Some((Ok(tok), span)) => Ok((tok, span)),
+            Some((Err(LexError::UnknownKey), span)) => self.fail(unknown_key, span),
+            Some((Err(LexError::CountOverflow), span)) => self.fail(count_overflow, span),
+            None => Err(Halt::Incomplete),
+        }
+    }
+
+    fn count(&mut self) -> Parsed<Option<(i32, Range<usize>)>> {
+        match self.tokens.peek() {
+            Some((Ok(Token::Mult(_)), _)) | Some((Err(LexError::CountOverflow), _)) => {
+                let (tok, span) = self.next()?;
+                let Token::Mult(count) = tok else {
+                    unreachable!("peeked a count")
+                };
+                Ok(Some((count, span)))
+            }
+            _ => Ok(None),
+        }
+    }
 
-    if let Some(Ok(tok)) = lex.next() {
-        direction_to_vector(tok, count).map(Action::MoveCursor)
-    } else {
-        None
+    fn action(&mut self) -> Parsed<Action> {
+        let count = self.count()?;
+        let (tok, span) = self.next()?;
+
+        let times = clamp_count(count.as_ref().map_or(1, |(c, _)| *c));
+        if self.mode == Mode::Visual {
+            if let Some(operator) = tok.visual_operator() {
+                return Ok(Action::Operate(operator, Motion::Selection));
+            }
+        }
+
+        match tok {
+            Token::Undo => return Ok(Action::Undo(times)),
+            Token::Redo => return Ok(Action::Redo(times)),
+            Token::Paint => return Ok(Action::Paint(times)),
+            Token::NextFrame => return Ok(Action::CycleFocus(times as i32)),
+            Token::Visual => return Ok(Action::Visual),
+            Token::Repeat => return Ok(Action::Repeat(count.map(|(c, _)| clamp_count(c)))),
+            Token::Colon => {
+                return match count {
+                    Some((_, dangling)) => self.fail(dangling_count, dangling),
+                    None => Ok(Action::CommandLine),
+                }
+            }
+            Token::Register(name) => {
+                return match count {
+                    Some((_, dangling)) => self.fail(dangling_count, dangling),
+                    None => Ok(Action::Register(name)),
+                }
+            }
+            Token::Quote => {
+                let (_, end) = self.next()?;
+                return self.fail(unknown_key, span.start..end.end);
+            }
+            Token::Record | Token::Play => {
+                if let (Token::Record, Some((_, dangling))) = (tok, &count) {
+                    return self.fail(dangling_count, dangling.clone());
+                }
+                // like buckets, the register after is read as typed
+                let Some((_, end)) = self.tokens.next() else {
+                    return Err(Halt::Incomplete);
+                };
+                let register = match &self.buffer[end.clone()] {
+                    "@" if tok == Token::Play => None,
+                    name => match name.chars().next() {
+                        Some(c @ 'a'..='z') if name.len() == 1 => Some(c),
+                        _ => return self.fail(unknown_key, span.start..end.end),
+                    },
+                };
+                return Ok(match register {
+                    Some(register) if tok == Token::Record => Action::Record(register),
+                    register => Action::PlayMacro {
+                        register,
+                        count: times,
+                    },
+                });
+            }
+            Token::PutAfter | Token::PutBefore => return Ok(put(tok, times, false)),
+            Token::Bucket => {
+                let connectivity = match &self.buffer[span.clone()] {
+                    "F" => Connectivity::Eight,
+                    _ => Connectivity::Four,
+                };
+                // the key after is read as typed, since most letters mean nothing else
+                let Some((_, end)) = self.tokens.next() else {
+                    return Err(Halt::Incomplete);
+                };
+                let by = match &self.buffer[end.clone()] {
+                    "g" => FillMatch::Glyph,
+                    "f" => FillMatch::Foreground,
+                    "b" => FillMatch::Background,
+                    "c" => FillMatch::Cell,
+                    _ => return self.fail(unknown_key, span.start..end.end),
+                };
+                return Ok(Action::FloodFill { connectivity, by });
+            }
+            Token::Shape if self.mode == Mode::Visual => {
+                let box_drawing = &self.buffer[span.clone()] == "S";
+                let Some((_, end)) = self.tokens.next() else {
+                    return Err(Halt::Incomplete);
+                };
+                let shape = match &self.buffer[end.clone()] {
+                    "l" => Shape::Line,
+                    "r" => Shape::Rectangle { filled: false },
+                    "R" => Shape::Rectangle { filled: true },
+                    "e" => Shape::Ellipse { filled: false },
+                    "E" => Shape::Ellipse { filled: true },
+                    _ => return self.fail(unknown_key, span.start..end.end),
+                };
+                return Ok(Action::Draw { shape, box_drawing });
+            }
+            Token::Go => {
+                if let Some((Ok(tok @ (Token::PutAfter | Token::PutBefore)), _)) =
+                    self.tokens.peek()
+                {
+                    let tok = *tok;
+                    self.tokens.next();
+                    return Ok(put(tok, times, true));
+                }
+            }
+            Token::Window => {
+                return match self.next()? {
+                    (Token::Window | Token::WindowNext, _) => Ok(Action::CycleFocus(times as i32)),
+                    (Token::WindowPrevious, _) => Ok(Action::CycleFocus(-(times as i32))),
+                    (_, end) => self.fail(unknown_key, span.start..end.end),
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(operator) = tok.operator() {
+            let count = match (count, self.count()?) {
+                (Some((a, _)), Some((b, span))) => match a.checked_mul(b) {
+                    Some(count) => Some((count, span)),
+                    None => return self.fail(count_overflow, span),
+                },
+                (a, b) => a.or(b),
+            };
+            let (tok, end) = self.next()?;
+            let motion = if tok.operator() == Some(operator) {
+                Motion::Rows(clamp_count(count.map_or(1, |(c, _)| c)))
+            } else if tok.is_motion() {
+                self.motion(tok, end, count.map(|(c, _)| c))?
+            } else {
+                return Err(Halt::Failed(ActionError::InvalidPairing {
+                    operator,
+                    buffer: self.buffer.into(),
+                    span: span.start..end.end,
+                }));
+            };
+            return Ok(Action::Operate(operator, motion));
+        }
+
+        Ok(match self.motion(tok, span, count.map(|(c, _)| c))? {
+            Motion::By(v) => Action::MoveCursor(v),
+            Motion::ToRow(row) => Action::JumpToRow(row),
+            Motion::Rows(_) | Motion::Selection => {
+                unreachable!("only produced alongside operators")
+            }
+        })
+    }
+
+    fn motion(&mut self, tok: Token, span: Range<usize>, count: Option<i32>) -> Parsed<Motion> {
+        let jump = |default| count.map_or(default, |c| Row::Nth(clamp_count(c).saturating_sub(1)));
+        match tok {
+            tok if tok.is_direction() => Ok(Motion::By(
+                direction_to_vector(tok, count.unwrap_or(1)).expect("token is a direction"),
+            )),
+            Token::Go => match self.next()? {
+                (Token::Go, _) => Ok(Motion::ToRow(jump(Row::First))),
+                (Token::Mult(_), dangling) => self.fail(dangling_count, dangling),
+                (_, end) => self.fail(unknown_key, span.start..end.end),
+            },
+            Token::LastRow => Ok(Motion::ToRow(jump(Row::Last))),
+            _ => self.fail(unknown_key, span),
+        }
     }
 }
 
-pub fn parse_action(buffer: &str) -> Option<Result<Action, ActionError>> {
-    None // TODO
+fn put(tok: Token, count: u16, skip_blank: bool) -> Action {
+    Action::Put {
+        count,
+        before: tok == Token::PutBefore,
+        skip_blank,
+    }
+}
+
+fn unknown_key(buffer: String, span: Range<usize>) -> ActionError {
+    ActionError::UnknownKey { buffer, span }
+}
+
+fn count_overflow(buffer: String, span: Range<usize>) -> ActionError {
+    ActionError::CountOverflow { buffer, span }
+}
+
+fn dangling_count(buffer: String, span: Range<usize>) -> ActionError {
+    ActionError::DanglingCount { buffer, span }
+}
+
+/// Counts beyond what a canvas could ever hold act the same as the largest one
+fn clamp_count(count: i32) -> u16 {
+    count.clamp(0, u16::MAX as i32) as u16
+}
+
+pub fn parse_move_cursor(input: &str) -> Option<Action> {
+    match parse_action(input, Mode::Normal) {
+        Some(Ok(a @ Action::MoveCursor(_))) => Some(a),
+        _ => None,
+    }
+}
+
+/// Parses an action from a buffer of keys, written in the notation of
+/// [`KeyEvent`](super::input::KeyEvent). Returns `None` while the buffer is the
+/// start of a valid action, so that more keys can be pushed onto it.
+pub fn parse_action(buffer: &str, mode: Mode) -> Option<Result<Action, ActionError>> {
+    if buffer.ends_with("<Esc>") {
+        return Some(Ok(Action::Cancel));
+    }
+
+    match ActionParser::new(buffer, mode).action() {
+        Ok(action) => Some(Ok(action)),
+        Err(Halt::Incomplete) => None,
+        Err(Halt::Failed(e)) => Some(Err(e)),
+    }
+}
+
+#[cfg(test)]
+mod actions_test {
+    use super::*;
+
+    #[test]
+    fn incomplete_buffers() {
+        for buffer in ["", "3", "d", "2d3", "g", "12g", "d2g", "<C-w>"] {
+            assert_eq!(parse_action(buffer, Mode::Normal), None, "{buffer:?}");
+        }
+    }
+
+    #[test]
+    fn motions() {
+        assert_eq!(
+            parse_action("l", Mode::Normal),
+            Some(Ok(Action::MoveCursor(Vector2D::new(1, 0))))
+        );
+        assert_eq!(
+            parse_action("3k", Mode::Normal),
+            Some(Ok(Action::MoveCursor(Vector2D::new(0, -3))))
+        );
+        assert_eq!(
+            parse_action("gg", Mode::Normal),
+            Some(Ok(Action::JumpToRow(Row::First)))
+        );
+        assert_eq!(
+            parse_action("G", Mode::Normal),
+            Some(Ok(Action::JumpToRow(Row::Last)))
+        );
+        assert_eq!(
+            parse_action("5G", Mode::Normal),
+            Some(Ok(Action::JumpToRow(Row::Nth(4))))
+        );
+    }
+
+    #[test]
+    fn special_keys() {
+        assert_eq!(
+            parse_action("3<Up>", Mode::Normal),
+            Some(Ok(Action::MoveCursor(Vector2D::new(0, -3))))
+        );
+        assert_eq!(
+            parse_action("d<Right>", Mode::Normal),
+            Some(Ok(Action::Operate(
+                Operator::Delete,
+                Motion::By(Vector2D::new(1, 0))
+            )))
+        );
+        for buffer in ["<Esc>", "3d2<Esc>"] {
+            assert_eq!(
+                parse_action(buffer, Mode::Normal),
+                Some(Ok(Action::Cancel)),
+                "{buffer:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            parse_action("dd", Mode::Normal),
+            Some(Ok(Action::Operate(Operator::Delete, Motion::Rows(1))))
+        );
+        assert_eq!(
+            parse_action("2d3l", Mode::Normal),
+            Some(Ok(Action::Operate(
+                Operator::Delete,
+                Motion::By(Vector2D::new(6, 0))
+            )))
+        );
+        assert_eq!(
+            parse_action("dgg", Mode::Normal),
+            Some(Ok(Action::Operate(
+                Operator::Delete,
+                Motion::ToRow(Row::First)
+            )))
+        );
+    }
+
+    #[test]
+    fn history() {
+        assert_eq!(parse_action("u", Mode::Normal), Some(Ok(Action::Undo(1))));
+        assert_eq!(parse_action("3u", Mode::Normal), Some(Ok(Action::Undo(3))));
+        assert_eq!(
+            parse_action("<C-r>", Mode::Normal),
+            Some(Ok(Action::Redo(1)))
+        );
+        assert_eq!(
+            parse_action("du", Mode::Normal),
+            Some(Err(ActionError::InvalidPairing {
+                operator: Operator::Delete,
+                buffer: "du".into(),
+                span: 0..2
+            }))
+        );
+    }
+
+    #[test]
+    fn painting() {
+        assert_eq!(parse_action(" ", Mode::Normal), Some(Ok(Action::Paint(1))));
+        assert_eq!(parse_action("4 ", Mode::Normal), Some(Ok(Action::Paint(4))));
+        assert!(matches!(
+            parse_action("d ", Mode::Normal),
+            Some(Err(ActionError::InvalidPairing { .. }))
+        ));
+    }
+
+    #[test]
+    fn focus() {
+        assert_eq!(
+            parse_action("<Tab>", Mode::Normal),
+            Some(Ok(Action::CycleFocus(1)))
+        );
+        assert_eq!(
+            parse_action("2<C-w>w", Mode::Normal),
+            Some(Ok(Action::CycleFocus(2)))
+        );
+        assert_eq!(
+            parse_action("<C-w><C-w>", Mode::Normal),
+            Some(Ok(Action::CycleFocus(1)))
+        );
+        assert_eq!(
+            parse_action("<C-w>W", Mode::Normal),
+            Some(Ok(Action::CycleFocus(-1)))
+        );
+        assert_eq!(
+            parse_action("<C-w>j", Mode::Normal).map(|r| r.map_err(|e| e.span())),
+            Some(Err(0..6))
+        );
+    }
+
+    #[test]
+    fn visual_mode() {
+        let visual = |buffer| parse_action(buffer, Mode::Visual);
+        assert_eq!(parse_action("v", Mode::Normal), Some(Ok(Action::Visual)));
+        assert_eq!(
+            parse_action("<C-v>", Mode::Normal),
+            Some(Ok(Action::Visual))
+        );
+        assert_eq!(visual("v"), Some(Ok(Action::Visual)));
+        assert_eq!(
+            visual("3j"),
+            Some(Ok(Action::MoveCursor(Vector2D::new(0, 3))))
+        );
+        for (buffer, operator) in [
+            ("d", Operator::Delete),
+            (" ", Operator::Fill),
+            ("r", Operator::Recolor),
+        ] {
+            assert_eq!(
+                visual(buffer),
+                Some(Ok(Action::Operate(operator, Motion::Selection))),
+                "{buffer:?}"
+            );
+        }
+
+        // outside of visual mode they need something to act on
+        assert_eq!(parse_action("d", Mode::Normal), None);
+        assert_eq!(parse_action(" ", Mode::Normal), Some(Ok(Action::Paint(1))));
+        assert!(matches!(
+            parse_action("r", Mode::Normal),
+            Some(Err(ActionError::UnknownKey { .. }))
+        ));
+    }
+
+    #[test]
+    fn registers() {
+        let put = |count, before, skip_blank| {
+            Some(Ok(Action::Put {
+                count,
+                before,
+                skip_blank,
+            }))
+        };
+        assert_eq!(
+            parse_action("\"a", Mode::Normal),
+            Some(Ok(Action::Register('a')))
+        );
+        assert_eq!(parse_action("p", Mode::Normal), put(1, false, false));
+        assert_eq!(parse_action("3P", Mode::Normal), put(3, true, false));
+        assert_eq!(parse_action("gp", Mode::Normal), put(1, false, true));
+        assert_eq!(
+            parse_action("y2l", Mode::Normal),
+            Some(Ok(Action::Operate(
+                Operator::Yank,
+                Motion::By(Vector2D::new(2, 0))
+            )))
+        );
+        assert_eq!(
+            parse_action("y", Mode::Visual),
+            Some(Ok(Action::Operate(Operator::Yank, Motion::Selection)))
+        );
+
+        assert_eq!(parse_action("\"", Mode::Normal), None);
+        for (buffer, span) in [("\"1", 0..2), ("2\"a", 0..1)] {
+            assert_eq!(
+                parse_action(buffer, Mode::Normal).map(|r| r.map_err(|e| e.span())),
+                Some(Err(span)),
+                "{buffer:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn flood_fills() {
+        let fill = |connectivity, by| Some(Ok(Action::FloodFill { connectivity, by }));
+        assert_eq!(parse_action("f", Mode::Normal), None);
+        assert_eq!(
+            parse_action("fg", Mode::Normal),
+            fill(Connectivity::Four, FillMatch::Glyph)
+        );
+        assert_eq!(
+            parse_action("Fb", Mode::Normal),
+            fill(Connectivity::Eight, FillMatch::Background)
+        );
+        assert_eq!(
+            parse_action("ff", Mode::Normal),
+            fill(Connectivity::Four, FillMatch::Foreground)
+        );
+        assert_eq!(
+            parse_action("Fc", Mode::Normal),
+            fill(Connectivity::Eight, FillMatch::Cell)
+        );
+        assert_eq!(
+            parse_action("fx", Mode::Normal).map(|r| r.map_err(|e| e.span())),
+            Some(Err(0..2))
+        );
+    }
+
+    #[test]
+    fn shapes() {
+        let draw = |shape, box_drawing| Some(Ok(Action::Draw { shape, box_drawing }));
+        assert_eq!(parse_action("s", Mode::Visual), None);
+        assert_eq!(parse_action("sl", Mode::Visual), draw(Shape::Line, false));
+        assert_eq!(
+            parse_action("SR", Mode::Visual),
+            draw(Shape::Rectangle { filled: true }, true)
+        );
+        assert_eq!(
+            parse_action("se", Mode::Visual),
+            draw(Shape::Ellipse { filled: false }, false)
+        );
+        assert!(matches!(
+            parse_action("sl", Mode::Normal),
+            Some(Err(ActionError::UnknownKey { .. }))
+        ));
+    }
+
+    #[test]
+    fn repeating() {
+        assert_eq!(
+            parse_action(".", Mode::Normal),
+            Some(Ok(Action::Repeat(None)))
+        );
+        assert_eq!(
+            parse_action("3.", Mode::Normal),
+            Some(Ok(Action::Repeat(Some(3))))
+        );
+
+        let delete = |motion| Action::Operate(Operator::Delete, motion);
+        assert!(delete(Motion::Rows(1)).is_change());
+        assert!(!Action::Operate(Operator::Yank, Motion::Rows(1)).is_change());
+        assert!(!Action::MoveCursor((1, 0).into()).is_change());
+
+        assert_eq!(Action::Paint(2).with_count(5), Action::Paint(5));
+        assert_eq!(
+            delete(Motion::By((-2, 0).into())).with_count(4),
+            delete(Motion::By((-4, 0).into()))
+        );
+        assert_eq!(
+            delete(Motion::Rows(2)).with_count(3),
+            delete(Motion::Rows(3))
+        );
+        assert_eq!(
+            delete(Motion::Selection).with_count(3),
+            delete(Motion::Selection)
+        );
+    }
+
+    #[test]
+    fn bindable_keys() {
+        // escape is looked for before the buffer is split into tokens
+        for (name, key) in BINDABLE.iter().filter(|(_, key)| *key != "<Esc>") {
+            let tokens: Vec<_> = Token::lexer(key).collect();
+            assert!(matches!(tokens[..], [Ok(_)]), "{name} is {tokens:?}");
+        }
+
+        assert!(takes_argument("3\""));
+        assert!(takes_argument("2F"));
+        assert!(!takes_argument("d"));
+        assert!(!takes_argument(""));
+    }
+
+    #[test]
+    fn macros() {
+        assert_eq!(parse_action("q", Mode::Normal), None);
+        assert_eq!(
+            parse_action("qa", Mode::Normal),
+            Some(Ok(Action::Record('a')))
+        );
+        assert_eq!(
+            parse_action("3@b", Mode::Normal),
+            Some(Ok(Action::PlayMacro {
+                register: Some('b'),
+                count: 3
+            }))
+        );
+        assert_eq!(
+            parse_action("@@", Mode::Normal),
+            Some(Ok(Action::PlayMacro {
+                register: None,
+                count: 1
+            }))
+        );
+        assert!(matches!(
+            parse_action("q@", Mode::Normal),
+            Some(Err(ActionError::UnknownKey { span, .. })) if span == (0..2)
+        ));
+        assert!(matches!(
+            parse_action("2qa", Mode::Normal),
+            Some(Err(ActionError::DanglingCount { span, .. })) if span == (0..1)
+        ));
+
+        assert_eq!(Mode::Visual.status(Some('a')), "-- VISUAL --recording @a");
+        assert_eq!(Mode::Normal.status(None), "");
+    }
+
+    #[test]
+    fn command_line() {
+        assert_eq!(
+            parse_action(":", Mode::Normal),
+            Some(Ok(Action::CommandLine))
+        );
+        assert_eq!(
+            parse_action(":", Mode::Visual),
+            Some(Ok(Action::CommandLine))
+        );
+        assert_eq!(
+            parse_action("3:", Mode::Normal),
+            Some(Err(ActionError::DanglingCount {
+                buffer: "3:".into(),
+                span: 0..1
+            }))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_action("3Q", Mode::Normal),
+            Some(Err(ActionError::UnknownKey {
+                buffer: "3Q".into(),
+                span: 1..2
+            }))
+        );
+        assert_eq!(
+            parse_action("gj", Mode::Normal).map(|r| r.map_err(|e| e.span())),
+            Some(Err(0..2))
+        );
+        assert_eq!(
+            parse_action("g3g", Mode::Normal),
+            Some(Err(ActionError::DanglingCount {
+                buffer: "g3g".into(),
+                span: 1..2
+            }))
+        );
+        assert!(matches!(
+            parse_action("99999999999l", Mode::Normal),
+            Some(Err(ActionError::CountOverflow { span, .. })) if span == (0..11)
+        ));
+        assert!(matches!(
+            parse_action("99999d99999l", Mode::Normal),
+            Some(Err(ActionError::CountOverflow { span, .. })) if span == (6..11)
+        ));
+    }
+
+    #[test]
+    fn error_messages() {
+        let e = parse_action("3Q", Mode::Normal).unwrap().unwrap_err();
+        assert_eq!(e.to_string(), "unknown command `3Q`");
+    }
 }
@@ -1,7 +1,8 @@
 use std::ops::{Index, IndexMut};
 
-use euclid::default::Size2D;
+use euclid::default::{Size2D, Vector2D};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Array2D<T>(Vec<T>, u16);
 
 /// coordinate to index
@@ -36,18 +37,26 @@ impl<T> Array2D<T> {
         self.0.as_mut_slice()
     }
 
-    pub fn index_to_coord(&self, i: usize) -> (u16, u16) {
-        let i = i as u16;
-        (i % self.1, i / self.1)
-    }
-
     pub fn sides(&self) -> Size2D<u16> {
         let height = self.0.len() as u16 / self.1;
         (self.1, height).into()
     }
+}
 
-    pub fn set_width(&mut self, width: u16) {
-        self.1 = width;
+impl<T: Clone> Array2D<T> {
+    /// Reallocates to a new size, keeping every item that still fits. `offset` is
+    /// where the old top left corner ends up, and new items are set to `fill`.
+    pub fn resize(&mut self, size: Size2D<u16>, offset: Vector2D<i32>, fill: T) {
+        let old = self.sides();
+        let mut data = Vec::with_capacity(size.width as usize * size.height as usize);
+        for y in 0..size.height as i32 {
+            for x in 0..size.width as i32 {
+                let (ox, oy) = (x - offset.x, y - offset.y);
+                let kept = (0..old.width as i32).contains(&ox) && (0..old.height as i32).contains(&oy);
+                data.push(if kept { self[[ox as u16, oy as u16]].clone() } else { fill.clone() });
+            }
+        }
+        *self = Self(data, size.width);
     }
 }
 
@@ -93,4 +102,15 @@ mod array_2d_test {
         a[[0, 0]] = MockType::B;
         assert_eq!(a[[0, 0]], MockType::B);
     }
+
+    #[test]
+    fn resizing() {
+        let mut a: Array2D<u8> = (vec![1, 2, 3, 4, 5, 6], 3).into();
+        a.resize((4, 3).into(), (1, 1).into(), 0);
+        assert_eq!(a.sides(), (4, 3).into());
+        assert_eq!(a.slice(), &[0, 0, 0, 0, 0, 1, 2, 3, 0, 4, 5, 6]);
+
+        a.resize((2, 1).into(), (-2, -1).into(), 9);
+        assert_eq!(a.slice(), &[2, 3]);
+    }
 }
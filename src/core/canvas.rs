@@ -1,13 +1,67 @@
-use super::{array2d::Array2D, CharID, Charset};
+use super::{
+    actions::{Action, Connectivity, FillMatch, Mode, Motion, Operator, Row, Shape},
+    array2d::Array2D,
+    history::History,
+    layers::{self, Blend, Layer, LayerCommand, LayerError},
+    registers::{Block, Registers},
+    shapes, CharID, Charset,
+};
 
-use euclid::default::Size2D;
+use euclid::default::{Box2D, Size2D, Vector2D};
 
 use super::CanvasPos;
 
+/// What a cursor does when it is moved past the edge of its bounds
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Stops at the last cell
+    #[default]
+    Clamp,
+    /// Comes back around from the opposite side
+    Wrap,
+}
+
+/// Which part of a canvas stays where it is when the canvas is resized
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Where the old top left corner lands when going from one size to another
+    fn offset(self, old: Size2D<u16>, new: Size2D<u16>) -> Vector2D<i32> {
+        let dw = new.width as i32 - old.width as i32;
+        let dh = new.height as i32 - old.height as i32;
+        let (x, y) = match self {
+            Self::TopLeft => (0, 0),
+            Self::Top => (dw / 2, 0),
+            Self::TopRight => (dw, 0),
+            Self::Left => (0, dh / 2),
+            Self::Center => (dw / 2, dh / 2),
+            Self::Right => (dw, dh / 2),
+            Self::BottomLeft => (0, dh),
+            Self::Bottom => (dw / 2, dh),
+            Self::BottomRight => (dw, dh),
+        };
+        Vector2D::new(x, y)
+    }
+}
+
 pub struct Cursor {
+    /// Where a selection was started from, the other corner is the cursor itself
     origin: Option<CanvasPos>,
     position: CanvasPos,
     bounds: Size2D<u16>,
+    edge: Edge,
 }
 
 impl Cursor {
@@ -16,17 +70,94 @@ impl Cursor {
             origin: None,
             position,
             bounds: (bottom_bound, right_bound).into(),
+            edge: Edge::default(),
         }
     }
 
     pub fn position(&self) -> CanvasPos {
         self.position
     }
+
+    /// The block of cells between the origin and the cursor, both included
+    pub fn selection(&self) -> Option<Box2D<u16>> {
+        self.origin.map(|o| {
+            let p = self.position;
+            Box2D::new(
+                (o.x.min(p.x), o.y.min(p.y)).into(),
+                (o.x.max(p.x) + 1, o.y.max(p.y) + 1).into(),
+            )
+        })
+    }
+
+    /// Where the cursor would end up after a motion, without the edge applied
+    fn target(&self, motion: Motion) -> (i32, i32) {
+        let (x, y) = (self.position.x as i32, self.position.y as i32);
+        match motion {
+            Motion::By(Vector2D { x: dx, y: dy, .. }) => {
+                (x.saturating_add(dx), y.saturating_add(dy))
+            }
+            Motion::ToRow(Row::First) => (x, 0),
+            Motion::ToRow(Row::Last) => (x, self.bounds.height as i32 - 1),
+            Motion::ToRow(Row::Nth(n)) => (x, n as i32),
+            Motion::Rows(n) => (x, y.saturating_add(n.max(1) as i32 - 1)),
+            Motion::Selection => (x, y),
+        }
+    }
+
+    /// Moves the cursor, keeping it inside of its bounds
+    pub fn apply_motion(&mut self, motion: Motion) {
+        if self.bounds.is_empty() {
+            return;
+        }
+
+        let (w, h) = (self.bounds.width as i32, self.bounds.height as i32);
+        let (x, y) = self.target(motion);
+        let (x, y) = match (self.edge, motion) {
+            // jumps always land on a real row
+            (Edge::Wrap, Motion::By(_)) => (x.rem_euclid(w), y.rem_euclid(h)),
+            _ => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+        };
+        self.position = (x as u16, y as u16).into();
+    }
+
+    /// The cells a motion passes over, like vim: sideways motions cover the cells
+    /// up to but excluding where they land, and every other motion covers whole rows.
+    pub fn motion_region(&self, motion: Motion) -> Box2D<u16> {
+        if motion == Motion::Selection {
+            let p = self.position;
+            return self
+                .selection()
+                .unwrap_or(Box2D::new(p, (p.x + 1, p.y + 1).into()));
+        }
+
+        let (w, h) = (self.bounds.width as i32, self.bounds.height as i32);
+        let (x, y) = (self.position.x as i32, self.position.y as i32);
+        let (tx, ty) = self.target(motion);
+
+        let (min, max) = if motion.is_linewise() {
+            ((0, y.min(ty).max(0)), (w, y.max(ty).min(h - 1) + 1))
+        } else {
+            let (start, end) = if tx < x {
+                (tx.max(0), x)
+            } else {
+                (x, tx.min(w))
+            };
+            ((start, y), (end, y + 1))
+        };
+        Box2D::new(
+            (min.0 as u16, min.1 as u16).into(),
+            (max.0 as u16, max.1 as u16).into(),
+        )
+    }
 }
 
 pub struct Canvas<C, A = ()> {
-    /// The full grid of items.
-    data: Array2D<(CharID, A)>,
+    /// Grids of items from the bottom of the stack to the top, there's always
+    /// at least one
+    layers: Vec<Layer<A>>,
+
+    /// Index of the layer that edits go to
+    active_layer: usize,
 
     /// Represents all possible values that can be placed on the Canvas.
     /// Meant to decouple the backend from the frontend, for example a TUI frontend
@@ -38,13 +169,21 @@ pub struct Canvas<C, A = ()> {
     ///  - Font canvas has a cursor for picking a character
     ///  - Palette canvas has a cursor for picking a character
     cursors: Vec<Cursor>,
+
+    /// Index of the cursor that motions are applied to
+    active_cursor: usize,
+
+    /// Edits that can be undone and redone
+    history: History<A>,
 }
 
 pub struct CanvasBuilder<C, A = ()> {
     size: Size2D<u16>,
     charset: C,
     cursor_positions: Vec<CanvasPos>,
+    cursor_edge: Edge,
     default_cells: Option<Vec<(CharID, A)>>,
+    layers: Vec<Layer<A>>,
 }
 
 impl<T, C, A> CanvasBuilder<C, A>
@@ -56,8 +195,10 @@ where
         Self {
             size: (charset.len(), 1).into(),
             cursor_positions: vec![],
+            cursor_edge: Edge::default(),
             charset,
             default_cells: None,
+            layers: vec![],
         }
     }
 
@@ -83,6 +224,12 @@ where
         self
     }
 
+    /// How every cursor on the canvas behaves at its edges
+    pub fn cursor_edge(mut self, edge: Edge) -> Self {
+        self.cursor_edge = edge;
+        self
+    }
+
     /// Uses a function to create the default members of a grid
     pub fn default_cells<F>(mut self, func: F) -> Self
     where
@@ -96,6 +243,13 @@ where
         self
     }
 
+    /// Stacks a layer on top of the ones already added. Without any, the canvas
+    /// gets a single layer made of the default cells.
+    pub fn layer(mut self, layer: Layer<A>) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
     /// Sets the first characters of a charset to be each character of the charset
     pub fn char_cascade(self) -> Self {
         self.default_cells(|id, c| (if id < c.len() { id } else { 0 }, A::default()))
@@ -109,59 +263,71 @@ where
         }
         let cursors = cursors
             .into_iter()
-            .map(|p| Cursor::new(p, width, height))
+            .map(|p| Cursor {
+                edge: self.cursor_edge,
+                ..Cursor::new(p, width, height)
+            })
             .collect();
 
-        let data = if let Some(default_cells) = self.default_cells {
-            (default_cells, width).into()
-        } else {
-            Array2D::new(width, height)
-        };
+        let mut layers = self.layers;
+        assert!(
+            layers.iter().all(|l| l.grid().sides() == self.size),
+            "layers must be the size of the canvas"
+        );
+        if layers.is_empty() {
+            let data = if let Some(default_cells) = self.default_cells {
+                (default_cells, width).into()
+            } else {
+                Array2D::new(width, height)
+            };
+            layers.push(Layer::from_grid("Layer 1", data));
+        }
 
         Canvas {
-            data,
+            layers,
+            active_layer: 0,
             charset: self.charset,
             cursors,
+            active_cursor: 0,
+            history: History::default(),
         }
     }
 }
 
-pub type Cell<'a, T, A> = (u16, u16, T, &'a A);
+pub type Cell<T, A> = (u16, u16, T, A);
 
 impl<T, C, A> Canvas<C, A>
 where
     C: Charset<Item = T>,
 {
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.data().len()
     }
 
+    /// The cells of the active layer
     pub fn iter(&self) -> impl Iterator<Item = &(CharID, A)> {
-        self.data.slice().iter()
+        self.data().slice().iter()
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut (CharID, A)> {
-        self.data.mut_slice().iter_mut()
+        self.data_mut().mut_slice().iter_mut()
     }
 
+    /// A cell of the active layer
     pub fn get(&self, x: u16, y: u16) -> &(CharID, A) {
-        &self.data[[x, y]]
+        &self.data()[[x, y]]
     }
 
     pub fn get_mut(&mut self, x: u16, y: u16) -> &mut (CharID, A) {
-        &mut self.data[[x, y]]
+        &mut self.data_mut()[[x, y]]
     }
 
-    /// Returns an iter of cells
-    pub fn cells(&self) -> impl Iterator<Item = Cell<T, A>> {
-        self.data
-            .slice()
-            .iter()
-            .enumerate()
-            .map(|(index, (id, attributes))| {
-                let (x, y) = self.data.index_to_coord(index);
-                (x, y, self.charset.get_char(*id), attributes)
-            })
+    fn data(&self) -> &Array2D<(CharID, A)> {
+        self.layers[self.active_layer].grid()
+    }
+
+    fn data_mut(&mut self) -> &mut Array2D<(CharID, A)> {
+        self.layers[self.active_layer].grid_mut()
     }
 
     pub fn charset(&self) -> &C {
@@ -169,16 +335,17 @@ where
     }
 
     pub fn size(&self) -> Size2D<u16> {
-        self.data.sides()
+        self.data().sides()
     }
 
-    /// Sets the new size, and updates the bounds of cursors
-    pub fn set_size(&mut self, new_size: Size2D<u16>) {
-        self.data.set_width(new_size.width);
+    /// Every layer, from the bottom of the stack to the top
+    pub fn layers(&self) -> &[Layer<A>] {
+        &self.layers
+    }
 
-        for c in self.cursors.iter_mut() {
-            c.bounds = new_size;
-        }
+    /// Index of the layer that edits go to
+    pub fn active_layer(&self) -> usize {
+        self.active_layer
     }
 
     /// Returns state of cursors on the grid. Contents are:
@@ -187,11 +354,491 @@ where
     pub fn cursors(&self) -> impl Iterator<Item = &Cursor> {
         self.cursors.iter()
     }
+
+    /// The cursor that motions are applied to
+    pub fn active_cursor(&self) -> Option<&Cursor> {
+        self.cursors.get(self.active_cursor)
+    }
+
+    /// Puts a cursor on a cell, keeping it within the canvas
+    pub fn place_cursor(&mut self, index: usize, position: CanvasPos) {
+        if let Some(c) = self.cursors.get_mut(index) {
+            c.position = CanvasPos::new(
+                position.x.min(c.bounds.width.saturating_sub(1)),
+                position.y.min(c.bounds.height.saturating_sub(1)),
+            );
+        }
+    }
+
+    /// Whether the active cursor is selecting
+    pub fn mode(&self) -> Mode {
+        match self.active_cursor().and_then(Cursor::selection) {
+            Some(_) => Mode::Visual,
+            None => Mode::Normal,
+        }
+    }
+
+    /// Where the active cursor's selection was started from, relative to the cursor
+    pub fn selection_offset(&self) -> Option<Vector2D<i32>> {
+        let cursor = self.active_cursor()?;
+        cursor.origin.map(|o| o.to_i32() - cursor.position.to_i32())
+    }
+
+    /// Selects a block shaped like a selection that started `offset` from the
+    /// cursor, with its top left corner where the cursor is now. This is how
+    /// `.` repeats a change made to a selection.
+    pub fn reselect(&mut self, offset: Vector2D<i32>) {
+        let Some(c) = self.cursors.get_mut(self.active_cursor) else {
+            return;
+        };
+        let (w, h) = (c.bounds.width as i32, c.bounds.height as i32);
+        let clamp = |p: Vector2D<i32>| {
+            CanvasPos::new(
+                p.x.clamp(0, (w - 1).max(0)) as u16,
+                p.y.clamp(0, (h - 1).max(0)) as u16,
+            )
+        };
+        let position = c.position.to_i32().to_vector() - offset.min(Vector2D::zero());
+        c.position = clamp(position);
+        c.origin = Some(clamp(position + offset));
+    }
+
+    /// The cell under each cursor, in the order the cursors were added
+    pub fn under_cursors(&self) -> impl Iterator<Item = &(CharID, A)> {
+        self.cursors
+            .iter()
+            .map(|c| self.get(c.position.x, c.position.y))
+    }
+}
+
+impl<T, C, A> Canvas<C, A>
+where
+    C: Charset<Item = T>,
+    A: Blend + Default + Clone + PartialEq,
+{
+    /// What a cell looks like with every visible layer stacked up
+    pub fn composite(&self, x: u16, y: u16) -> (CharID, A) {
+        let (bottom, above) = self.layers.split_first().expect("canvas has no layers");
+        let mut cell = if bottom.shows() {
+            bottom.grid()[[x, y]].clone()
+        } else {
+            (0, A::default())
+        };
+        for layer in above.iter().filter(|l| l.shows()) {
+            cell = layers::composite(&cell, &layer.grid()[[x, y]], layer.opacity);
+        }
+        cell
+    }
+
+    /// Returns an iter of cells, as they look with the layers stacked up
+    pub fn cells(&self) -> impl Iterator<Item = Cell<T, A>> + '_ {
+        let size = self.size();
+        (0..size.height).flat_map(move |y| {
+            (0..size.width).map(move |x| {
+                let (id, attributes) = self.composite(x, y);
+                (x, y, self.charset.get_char(id), attributes)
+            })
+        })
+    }
+
+    /// Changes the stack of layers. Adding, deleting, moving or merging layers
+    /// can't be undone, so the history is forgotten.
+    pub fn apply_layer(&mut self, command: LayerCommand) -> Result<(), LayerError> {
+        let count = self.layers.len();
+        let active = self.active_layer;
+        let layer = &mut self.layers[active];
+        match command {
+            LayerCommand::Select(n) => {
+                if !(1..=count).contains(&n) {
+                    return Err(LayerError::NoSuchLayer(n, count));
+                }
+                self.active_layer = n - 1;
+                return Ok(());
+            }
+            LayerCommand::Visible(visible) => layer.visible = visible,
+            LayerCommand::Locked(locked) => layer.locked = locked,
+            LayerCommand::Opacity(opacity) => layer.opacity = opacity.min(100),
+            LayerCommand::New(name) => {
+                let name = name.unwrap_or_else(|| format!("Layer {}", count + 1));
+                self.layers
+                    .insert(active + 1, Layer::blank(name, self.size()));
+                self.active_layer += 1;
+                self.history.clear();
+            }
+            LayerCommand::Delete => {
+                if count == 1 {
+                    return Err(LayerError::LastLayer);
+                }
+                if layer.locked {
+                    return Err(LayerError::Locked(layer.name.clone()));
+                }
+                self.layers.remove(active);
+                self.active_layer = active.saturating_sub(1);
+                self.history.clear();
+            }
+            LayerCommand::Raise | LayerCommand::Lower => {
+                let other = match command {
+                    LayerCommand::Raise => Some(active + 1),
+                    _ => active.checked_sub(1),
+                };
+                if let Some(other) = other.filter(|&o| o < count) {
+                    self.layers.swap(active, other);
+                    self.active_layer = other;
+                    self.history.clear();
+                }
+            }
+            LayerCommand::Merge => {
+                let Some(below) = active.checked_sub(1) else {
+                    return Err(LayerError::NothingBelow);
+                };
+                if self.layers[below].locked {
+                    return Err(LayerError::Locked(self.layers[below].name.clone()));
+                }
+                // only what shows is kept, like flattening an image
+                let above = self.layers.remove(active);
+                if above.shows() {
+                    let grid = self.layers[below].grid_mut();
+                    for (cell, top) in grid.mut_slice().iter_mut().zip(above.cells()) {
+                        *cell = layers::composite(cell, top, above.opacity);
+                    }
+                }
+                self.active_layer = below;
+                self.history.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T, C, A> Canvas<C, A>
+where
+    C: Charset<Item = T>,
+    A: Default + Clone + PartialEq,
+{
+    /// Overwrites a cell of the active layer, remembering what it was so it can
+    /// be undone. Locked layers are left alone.
+    pub fn put(&mut self, x: u16, y: u16, cell: (CharID, A)) {
+        let layer = self.active_layer;
+        if self.layers[layer].locked {
+            return;
+        }
+        let before = self.get_mut(x, y);
+        if *before != cell {
+            let before = std::mem::replace(before, cell.clone());
+            self.history.record(layer, (x, y).into(), before, cell);
+        }
+    }
+
+    /// Changes the size of the canvas, keeping the cells on the anchored side where
+    /// they are and filling new ones with `fill`. Cursors move along with the
+    /// cells under them, while layers above the bottom one stay transparent.
+    /// Resizing can't be undone, so the history is forgotten.
+    pub fn resize(&mut self, size: Size2D<u16>, anchor: Anchor, fill: (CharID, A)) {
+        let offset = anchor.offset(self.size(), size);
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            let fill = match i {
+                0 => fill.clone(),
+                _ => (0, A::default()),
+            };
+            layer.resize(size, offset, fill);
+        }
+        self.history.clear();
+
+        let (w, h) = (size.width as i32, size.height as i32);
+        for c in self.cursors.iter_mut() {
+            let x = (c.position.x as i32 + offset.x).min(w - 1).max(0);
+            let y = (c.position.y as i32 + offset.y).min(h - 1).max(0);
+            c.position = (x as u16, y as u16).into();
+            c.bounds = size;
+            c.origin = None;
+        }
+    }
+
+    /// Ends the current undo step, every edit since the last commit is undone together
+    pub fn commit(&mut self) {
+        self.history.commit();
+    }
+
+    /// Resets every cell in the region back to the first character of the charset
+    pub fn clear(&mut self, region: Box2D<u16>) {
+        for y in region.y_range() {
+            for x in region.x_range() {
+                self.put(x, y, (0, A::default()));
+            }
+        }
+    }
+
+    /// Stamps `cell` over the region connected to the active cursor, made of the
+    /// cells that `same` says match the one under it. It's undone as a single step.
+    pub fn flood_fill<F>(&mut self, connectivity: Connectivity, same: F, cell: (CharID, A))
+    where
+        F: Fn(&(CharID, A), &(CharID, A)) -> bool,
+    {
+        let Some(start) = self.active_cursor().map(|c| c.position) else {
+            return;
+        };
+        let size = self.size();
+        let index = |p: CanvasPos| p.y as usize * size.width as usize + p.x as usize;
+        let target = self.get(start.x, start.y).clone();
+
+        let mut seen = vec![false; size.width as usize * size.height as usize];
+        seen[index(start)] = true;
+        let mut stack = vec![start];
+        while let Some(p) = stack.pop() {
+            self.put(p.x, p.y, cell.clone());
+            for (dx, dy) in connectivity.offsets() {
+                let (x, y) = (p.x as i32 + dx, p.y as i32 + dy);
+                if x < 0 || y < 0 || x >= size.width as i32 || y >= size.height as i32 {
+                    continue;
+                }
+                let next = CanvasPos::new(x as u16, y as u16);
+                if !seen[index(next)] && same(&target, self.get(next.x, next.y)) {
+                    seen[index(next)] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        self.commit();
+    }
+
+    /// Draws a shape between the active cursor and where its selection started,
+    /// which it ends. With `box_drawing`, edges and corners get the charset's line
+    /// characters when it has them, and the brush's glyph otherwise.
+    pub fn draw_shape(&mut self, shape: Shape, box_drawing: bool, brush: (CharID, A)) {
+        let Some(cursor) = self.cursors.get_mut(self.active_cursor) else {
+            return;
+        };
+        let to = cursor.position;
+        let from = cursor.origin.take().unwrap_or(to);
+        let min = CanvasPos::new(from.x.min(to.x), from.y.min(to.y));
+        let max = CanvasPos::new(from.x.max(to.x), from.y.max(to.y));
+
+        let glyphs = self.charset.box_glyphs().filter(|_| box_drawing);
+        let cells = match shape {
+            Shape::Line => shapes::line(from, to),
+            Shape::Rectangle { filled } => shapes::rectangle(min, max, filled),
+            Shape::Ellipse { filled } => shapes::ellipse(min, max, filled),
+        };
+        for p in cells {
+            let part = match shape {
+                // lines are straight along a single row or column
+                Shape::Line if min.y == max.y || min.x == max.x => shapes::box_part(p, min, max),
+                Shape::Rectangle { .. } => shapes::box_part(p, min, max),
+                Shape::Line | Shape::Ellipse { .. } => None,
+            };
+            let id = match (glyphs, part) {
+                (Some(glyphs), Some(part)) => glyphs.get(part),
+                _ => brush.0,
+            };
+            self.put(p.x, p.y, (id, brush.1.clone()));
+        }
+        self.commit();
+    }
+
+    /// Copies out the cells of a region
+    pub fn copy(&self, region: Box2D<u16>) -> Block<A> {
+        let cells = region
+            .y_range()
+            .flat_map(|y| region.x_range().map(move |x| self.get(x, y).clone()))
+            .collect();
+        Block::new(region.size(), cells)
+    }
+
+    /// Stamps a block with its top left corner on a cell, cutting off whatever
+    /// hangs over the edge. Doesn't end the undo step.
+    pub fn paste(&mut self, block: &Block<A>, at: CanvasPos, skip_blank: bool) {
+        let size = self.size();
+        for y in 0..block.size().height {
+            for x in 0..block.size().width {
+                let (cx, cy) = (at.x as u32 + x as u32, at.y as u32 + y as u32);
+                let cell = block.get(x, y);
+                let blank = skip_blank && cell.0 == 0;
+                if cx < size.width as u32 && cy < size.height as u32 && !blank {
+                    self.put(cx as u16, cy as u16, cell.clone());
+                }
+            }
+        }
+    }
+
+    /// Carries out an action like [`apply`](Self::apply), with somewhere to keep
+    /// yanked cells
+    pub fn apply_with(&mut self, action: Action, registers: &mut Registers<A>) {
+        match action {
+            Action::Register(name) => registers.select(name),
+            Action::Operate(operator @ (Operator::Yank | Operator::Delete), motion) => {
+                let region = self.operate_on(motion);
+                registers.store(self.copy(region));
+                if operator == Operator::Delete {
+                    self.clear(region);
+                    self.commit();
+                }
+            }
+            Action::Put {
+                count,
+                before,
+                skip_blank,
+            } => {
+                let Some(block) = registers.take() else {
+                    return;
+                };
+                let Some(cursor) = self.cursors.get_mut(self.active_cursor) else {
+                    return;
+                };
+                cursor.origin = None;
+                let mut at = cursor.position;
+                if !before {
+                    at.x = at.x.saturating_add(1);
+                }
+
+                let width = block.size().width;
+                for i in 0..count {
+                    let x = at.x as u32 + i as u32 * width as u32;
+                    if x >= self.size().width as u32 {
+                        break;
+                    }
+                    self.paste(block, CanvasPos::new(x as u16, at.y), skip_blank);
+                }
+                self.place_cursor(self.active_cursor, at);
+                self.commit();
+            }
+            action => self.apply(action),
+        }
+    }
+
+    /// Replaces every cell a motion covers with what `f` makes of it, as a single
+    /// undo step
+    pub fn transform<F>(&mut self, motion: Motion, f: F)
+    where
+        F: Fn(&(CharID, A)) -> (CharID, A),
+    {
+        let region = self.operate_on(motion);
+        for y in region.y_range() {
+            for x in region.x_range() {
+                let cell = f(self.get(x, y));
+                self.put(x, y, cell);
+            }
+        }
+        self.commit();
+    }
+
+    /// The region an operator acts on. Moves the active cursor to the start of
+    /// it and stops selecting, since the selection has been used up.
+    fn operate_on(&mut self, motion: Motion) -> Box2D<u16> {
+        let Some(cursor) = self.cursors.get_mut(self.active_cursor) else {
+            return Box2D::zero();
+        };
+
+        let region = cursor.motion_region(motion);
+        if motion == Motion::Selection {
+            cursor.position = region.min;
+        } else if motion.is_linewise() {
+            cursor.position.y = region.min.y;
+        } else {
+            cursor.position.x = region.min.x;
+        }
+        cursor.origin = None;
+        region
+    }
+
+    /// Stamps a cell at the active cursor and onto the `count - 1` cells to its
+    /// right, stopping at the edge. It's undone as a single step.
+    pub fn paint(&mut self, cell: (CharID, A), count: u16) {
+        let Some(p) = self.active_cursor().map(|c| c.position) else {
+            return;
+        };
+        let end = p.x.saturating_add(count).min(self.size().width);
+        for x in p.x..end {
+            self.put(x, p.y, cell.clone());
+        }
+        self.commit();
+    }
+
+    /// Carries out an action that [needs a brush](Action::needs_brush), stamping
+    /// `brush`. Flood fills spread between cells that `same` says match in the
+    /// way the action asks for. Other actions are left alone.
+    pub fn apply_brush<F>(&mut self, action: Action, brush: (CharID, A), same: F)
+    where
+        F: Fn(FillMatch, &(CharID, A), &(CharID, A)) -> bool,
+    {
+        match action {
+            Action::Paint(count) => self.paint(brush, count),
+            Action::Operate(Operator::Fill, motion) => self.transform(motion, |_| brush.clone()),
+            Action::Operate(Operator::Recolor, motion) => {
+                self.transform(motion, |(id, _)| (*id, brush.1.clone()))
+            }
+            Action::FloodFill { connectivity, by } => {
+                self.flood_fill(connectivity, |a, b| same(by, a, b), brush)
+            }
+            Action::Draw { shape, box_drawing } => self.draw_shape(shape, box_drawing, brush),
+            _ => {}
+        }
+    }
+
+    /// Carries out an action with the active cursor. Any edits it makes become
+    /// a single undo step.
+    pub fn apply(&mut self, action: Action) {
+        let active = self.active_cursor;
+        let Some(cursor) = self.cursors.get_mut(active) else {
+            return;
+        };
+
+        match action {
+            Action::MoveCursor(offset) => cursor.apply_motion(Motion::By(offset)),
+            Action::JumpToRow(row) => cursor.apply_motion(Motion::ToRow(row)),
+            Action::Operate(Operator::Delete, motion) => {
+                let region = self.operate_on(motion);
+                self.clear(region);
+            }
+            // registers outlive the canvas, so these go through `apply_with`
+            Action::Operate(Operator::Yank, _) | Action::Register(_) | Action::Put { .. } => {}
+            // these need the brush, which comes from outside the canvas, so they
+            // go through `apply_brush`
+            Action::Operate(Operator::Fill | Operator::Recolor, _) => {}
+            Action::FloodFill { .. } | Action::Draw { .. } => {}
+            Action::Undo(count) => {
+                self.history.commit();
+                // a step on a locked layer is left alone, the same as edits to it
+                for _ in 0..count {
+                    match self.history.undo(&mut self.layers) {
+                        Ok(Some(p)) => cursor.position = p,
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+            }
+            Action::Redo(count) => {
+                for _ in 0..count {
+                    match self.history.redo(&mut self.layers) {
+                        Ok(Some(p)) => cursor.position = p,
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+            }
+            Action::Paint(_) => {}
+            // focus moves between canvases, which the frontend arranges
+            Action::CycleFocus(_) => {}
+            Action::Cancel => cursor.origin = None,
+            Action::Visual => {
+                cursor.origin = match cursor.origin {
+                    Some(_) => None,
+                    None => Some(cursor.position),
+                }
+            }
+            // the main loop runs the command line and macros, and commands work
+            // on whole files, which only the frontends know about
+            Action::CommandLine | Action::Record(_) | Action::PlayMacro { .. } => {}
+            Action::Command(_) => {}
+            // the frontend keeps the last change, along with the brush it was made with
+            Action::Repeat(_) => {}
+        }
+
+        self.commit();
+    }
 }
 
 #[cfg(test)]
 pub(crate) mod canvas_model_test {
     use super::*;
+    use crate::core::cp437::Cp437;
     use std::collections::HashMap;
 
     #[derive(Clone)]
@@ -234,14 +881,23 @@ pub(crate) mod canvas_model_test {
         Green,
     }
 
+    impl Blend for Soil {
+        fn blend(&self, _below: &Self, _opacity: f32) -> Self {
+            self.clone()
+        }
+    }
+
     type Cell = (u16, Soil);
 
     #[test]
     fn putting_in_attributes() {
         let mut canvas = Canvas {
-            data: Array2D::<Cell>::new(8, 8),
+            layers: vec![Layer::<Soil>::blank("", (8, 8).into())],
+            active_layer: 0,
             charset: MockCharset { map: flowers_map() },
             cursors: vec![],
+            active_cursor: 0,
+            history: History::default(),
         };
 
         assert_eq!(
@@ -252,4 +908,317 @@ pub(crate) mod canvas_model_test {
         canvas.get_mut(4, 4).1 = Soil::Green;
         assert_eq!(canvas.get(4, 4), &(1, Soil::Green));
     }
+
+    fn garden(edge: Edge) -> Canvas<MockCharset<Flowers>, Soil> {
+        CanvasBuilder::init(MockCharset { map: flowers_map() })
+            .size((4, 3).into())
+            .cursor_edge(edge)
+            .default_cells(|_, _| (3, Soil::Green))
+            .build()
+    }
+
+    fn cursor_at<C: Charset>(canvas: &Canvas<C, Soil>) -> (u16, u16) {
+        canvas.active_cursor().unwrap().position().into()
+    }
+
+    #[test]
+    fn cursor_stays_in_bounds() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.apply(Action::MoveCursor((10, 1).into()));
+        assert_eq!(cursor_at(&canvas), (3, 1));
+        canvas.apply(Action::MoveCursor((0, -5).into()));
+        assert_eq!(cursor_at(&canvas), (3, 0));
+        canvas.apply(Action::JumpToRow(Row::Last));
+        assert_eq!(cursor_at(&canvas), (3, 2));
+
+        canvas.place_cursor(0, CanvasPos::new(9, 1));
+        assert_eq!(cursor_at(&canvas), (3, 1));
+
+        let mut canvas = garden(Edge::Wrap);
+        canvas.apply(Action::MoveCursor((-1, 0).into()));
+        assert_eq!(cursor_at(&canvas), (3, 0));
+        canvas.apply(Action::MoveCursor((2, 4).into()));
+        assert_eq!(cursor_at(&canvas), (1, 1));
+    }
+
+    #[test]
+    fn deleting_regions() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.apply(Action::MoveCursor((1, 0).into()));
+        canvas.apply(Action::Operate(Operator::Delete, Motion::By((5, 0).into())));
+        let row: Vec<_> = (0..4).map(|x| canvas.get(x, 0).0).collect();
+        assert_eq!(row, vec![3, 0, 0, 0]);
+
+        canvas.apply(Action::JumpToRow(Row::Last));
+        canvas.apply(Action::Operate(
+            Operator::Delete,
+            Motion::By((0, -1).into()),
+        ));
+        assert_eq!(cursor_at(&canvas), (1, 1));
+        assert!((0..4).all(|x| canvas.get(x, 1).0 == 0 && canvas.get(x, 2).0 == 0));
+    }
+
+    #[test]
+    fn undoing_and_redoing() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.apply(Action::Operate(Operator::Delete, Motion::Rows(1)));
+        canvas.apply(Action::MoveCursor((2, 2).into()));
+        canvas.apply(Action::Operate(Operator::Delete, Motion::By((1, 0).into())));
+        assert_eq!(canvas.get(2, 2).0, 0);
+
+        canvas.apply(Action::Undo(2));
+        assert!(canvas.iter().all(|(id, _)| *id == 3));
+        assert_eq!(cursor_at(&canvas), (0, 0));
+
+        canvas.apply(Action::Redo(1));
+        assert_eq!(canvas.get(3, 0).0, 0);
+        assert_eq!(canvas.get(2, 2).0, 3);
+
+        // a new edit forgets whatever could have been redone
+        canvas.apply(Action::Operate(Operator::Delete, Motion::Rows(3)));
+        canvas.apply(Action::Redo(1));
+        canvas.apply(Action::Undo(1));
+        assert_eq!(canvas.get(2, 2).0, 3);
+        assert_eq!(canvas.get(3, 0).0, 0);
+    }
+
+    #[test]
+    fn painting() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.apply(Action::MoveCursor((2, 1).into()));
+        canvas.paint((1, Soil::Brown), 5);
+        let row: Vec<_> = (0..4).map(|x| canvas.get(x, 1).clone()).collect();
+        assert_eq!(
+            row,
+            vec![
+                (3, Soil::Green),
+                (3, Soil::Green),
+                (1, Soil::Brown),
+                (1, Soil::Brown)
+            ]
+        );
+
+        canvas.apply(Action::Undo(1));
+        assert!(canvas.iter().all(|c| *c == (3, Soil::Green)));
+    }
+
+    #[test]
+    fn selecting_blocks() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.apply(Action::MoveCursor((2, 0).into()));
+        canvas.apply(Action::Visual);
+        assert_eq!(canvas.mode(), Mode::Visual);
+        canvas.apply(Action::MoveCursor((-1, 1).into()));
+        let selection = canvas.active_cursor().unwrap().selection();
+        assert_eq!(selection, Some(Box2D::new((1, 0).into(), (3, 2).into())));
+
+        canvas.transform(Motion::Selection, |&(id, _)| (id, Soil::Brown));
+        assert_eq!(canvas.mode(), Mode::Normal);
+        assert_eq!(cursor_at(&canvas), (1, 0));
+        let brown = canvas
+            .iter()
+            .filter(|(_, soil)| *soil == Soil::Brown)
+            .count();
+        assert_eq!(brown, 4);
+        assert_eq!(canvas.get(2, 1), &(3, Soil::Brown));
+
+        canvas.apply(Action::Visual);
+        canvas.apply(Action::JumpToRow(Row::Last));
+        canvas.apply(Action::Operate(Operator::Delete, Motion::Selection));
+        assert!((0..3).all(|y| canvas.get(1, y).0 == 0 && canvas.get(2, y).0 == 3));
+
+        canvas.apply(Action::Visual);
+        canvas.apply(Action::Cancel);
+        assert_eq!(canvas.mode(), Mode::Normal);
+    }
+
+    #[test]
+    fn yanking_and_putting() {
+        let mut canvas = garden(Edge::Clamp);
+        let mut registers = Registers::default();
+        canvas.put(1, 0, (1, Soil::Brown));
+        canvas.apply(Action::Visual);
+        canvas.apply(Action::MoveCursor((1, 1).into()));
+        canvas.apply_with(Action::Register('a'), &mut registers);
+        canvas.apply_with(
+            Action::Operate(Operator::Yank, Motion::Selection),
+            &mut registers,
+        );
+        assert_eq!(cursor_at(&canvas), (0, 0));
+
+        canvas.apply_with(
+            Action::Operate(Operator::Delete, Motion::Rows(3)),
+            &mut registers,
+        );
+        assert!(canvas.iter().all(|(id, _)| *id == 0));
+
+        let put = |count, before, skip_blank| Action::Put {
+            count,
+            before,
+            skip_blank,
+        };
+        canvas.apply_with(Action::Register('a'), &mut registers);
+        canvas.apply_with(put(2, false, false), &mut registers);
+        assert_eq!(cursor_at(&canvas), (1, 0));
+        let row: Vec<_> = (0..4).map(|x| canvas.get(x, 0).0).collect();
+        assert_eq!(row, vec![0, 3, 1, 3]);
+        assert_eq!(canvas.get(2, 1), &(3, Soil::Green));
+
+        // blank cells can be left out, so what's under them shows through
+        canvas.apply(Action::MoveCursor((-1, 0).into()));
+        let yank = Action::Operate(Operator::Yank, Motion::By((2, 0).into()));
+        canvas.apply_with(yank, &mut registers);
+        canvas.apply(Action::MoveCursor((2, 0).into()));
+        canvas.apply_with(put(1, true, true), &mut registers);
+        assert_eq!(canvas.get(2, 0), &(1, Soil::Brown));
+        canvas.apply_with(put(1, true, false), &mut registers);
+        assert_eq!(canvas.get(2, 0), &(0, Soil::Brown));
+        canvas.apply(Action::Undo(1));
+        assert_eq!(canvas.get(2, 0), &(1, Soil::Brown));
+        assert_eq!(canvas.get(3, 0), &(3, Soil::Green));
+    }
+
+    #[test]
+    fn flood_filling() {
+        let mut canvas = garden(Edge::Clamp);
+        // roses fence in the first cell, except across the diagonal
+        canvas.put(1, 0, (1, Soil::Green));
+        canvas.put(0, 1, (1, Soil::Brown));
+        canvas.commit();
+
+        let same_flower = |a: &Cell, b: &Cell| a.0 == b.0;
+        canvas.flood_fill(Connectivity::Four, same_flower, (2, Soil::Brown));
+        let peonies = canvas.iter().filter(|(id, _)| *id == 2).count();
+        assert_eq!(peonies, 1);
+
+        canvas.apply(Action::Undo(1));
+        canvas.flood_fill(Connectivity::Eight, same_flower, (2, Soil::Brown));
+        let peonies = canvas.iter().filter(|(id, _)| *id == 2).count();
+        assert_eq!(peonies, 10);
+
+        // matching on the whole cell doesn't reach the brown rose
+        canvas.apply(Action::Undo(1));
+        canvas.apply(Action::MoveCursor((1, 0).into()));
+        canvas.flood_fill(Connectivity::Eight, |a, b| a == b, (0, Soil::Brown));
+        assert_eq!(canvas.get(1, 0).0, 0);
+        assert_eq!(canvas.get(0, 1).0, 1);
+    }
+
+    #[test]
+    fn drawing_shapes() {
+        let mut canvas = CanvasBuilder::<_, ()>::init(Cp437)
+            .size((5, 3).into())
+            .build();
+        let rows = |canvas: &Canvas<Cp437>| -> Vec<String> {
+            (0..3)
+                .map(|y| {
+                    (0..5)
+                        .map(|x| Cp437::TABLE[canvas.get(x, y).0 as usize])
+                        .collect()
+                })
+                .collect()
+        };
+
+        canvas.apply(Action::Visual);
+        canvas.apply(Action::MoveCursor((4, 2).into()));
+        let box_outline = Shape::Rectangle { filled: false };
+        canvas.draw_shape(box_outline, true, (b'#' as u16, ()));
+        assert_eq!(rows(&canvas), vec!["┌───┐", "│   │", "└───┘"]);
+        assert_eq!(canvas.mode(), Mode::Normal);
+
+        canvas.apply(Action::Undo(1));
+        canvas.apply(Action::MoveCursor((-4, 2).into()));
+        canvas.apply(Action::Visual);
+        canvas.apply(Action::MoveCursor((4, -2).into()));
+        canvas.draw_shape(Shape::Line, true, (b'#' as u16, ()));
+        assert_eq!(rows(&canvas), vec!["   ##", " ##  ", "#    "]);
+    }
+
+    #[test]
+    fn resizing() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.put(0, 0, (1, Soil::Brown));
+        canvas.apply(Action::MoveCursor((3, 2).into()));
+        canvas.resize((6, 4).into(), Anchor::Center, (0, Soil::Green));
+        assert_eq!(canvas.size(), (6, 4).into());
+        assert_eq!(canvas.get(1, 0), &(1, Soil::Brown));
+        assert_eq!(canvas.get(0, 0), &(0, Soil::Green));
+        assert_eq!(canvas.get(4, 2), &(3, Soil::Green));
+        assert_eq!(cursor_at(&canvas), (4, 2));
+
+        canvas.resize((2, 2).into(), Anchor::BottomRight, (0, Soil::Green));
+        assert_eq!(canvas.iter().filter(|(id, _)| *id == 3).count(), 1);
+        assert_eq!(cursor_at(&canvas), (0, 0));
+
+        // there's nothing left to undo
+        canvas.apply(Action::Undo(1));
+        assert_eq!(canvas.get(0, 0).0, 3);
+    }
+
+    #[test]
+    fn layering() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.apply_layer(LayerCommand::New(None)).unwrap();
+        assert_eq!(canvas.active_layer(), 1);
+        // new layers are see-through until they're drawn on
+        assert_eq!(canvas.composite(0, 0), (3, Soil::Green));
+        canvas.put(0, 0, (1, Soil::Brown));
+        assert_eq!(canvas.composite(0, 0), (1, Soil::Brown));
+        assert_eq!(canvas.layers()[0].cells()[0], (3, Soil::Green));
+
+        canvas.apply_layer(LayerCommand::Visible(false)).unwrap();
+        assert_eq!(canvas.composite(0, 0), (3, Soil::Green));
+        canvas.apply_layer(LayerCommand::Visible(true)).unwrap();
+
+        canvas.commit();
+        canvas.apply_layer(LayerCommand::Locked(true)).unwrap();
+        canvas.put(1, 0, (2, Soil::Brown));
+        assert_eq!(canvas.get(1, 0).0, 0);
+        // nor can what was drawn on it before be undone
+        canvas.apply(Action::Undo(1));
+        assert_eq!(canvas.get(0, 0), &(1, Soil::Brown));
+        assert_eq!(
+            canvas.apply_layer(LayerCommand::Delete),
+            Err(LayerError::Locked("Layer 2".into()))
+        );
+        canvas.apply_layer(LayerCommand::Locked(false)).unwrap();
+
+        canvas.apply_layer(LayerCommand::Lower).unwrap();
+        assert_eq!(canvas.active_layer(), 0);
+        assert_eq!(canvas.composite(0, 0), (3, Soil::Green));
+        assert_eq!(
+            canvas.apply_layer(LayerCommand::Merge),
+            Err(LayerError::NothingBelow)
+        );
+        canvas.apply_layer(LayerCommand::Raise).unwrap();
+
+        canvas.apply_layer(LayerCommand::Merge).unwrap();
+        assert_eq!(canvas.layers().len(), 1);
+        assert_eq!(canvas.get(0, 0), &(1, Soil::Brown));
+        assert_eq!(canvas.get(1, 0), &(3, Soil::Green));
+        assert_eq!(
+            canvas.apply_layer(LayerCommand::Delete),
+            Err(LayerError::LastLayer)
+        );
+        assert_eq!(
+            canvas.apply_layer(LayerCommand::Select(3)),
+            Err(LayerError::NoSuchLayer(3, 1))
+        );
+    }
+
+    #[test]
+    fn history_is_capped() {
+        let mut canvas = garden(Edge::Clamp);
+        canvas.history = History::new(6);
+        for _ in 0..3 {
+            canvas.apply(Action::Operate(Operator::Delete, Motion::Rows(1)));
+            canvas.apply(Action::MoveCursor((0, 1).into()));
+        }
+
+        // each step changes 4 cells, so only the last one fits
+        canvas.apply(Action::Undo(3));
+        assert_eq!(canvas.get(0, 0).0, 0);
+        assert_eq!(canvas.get(0, 1).0, 0);
+        assert_eq!(canvas.get(0, 2).0, 3);
+    }
 }
@@ -0,0 +1,286 @@
+use std::path::PathBuf;
+
+use euclid::default::Size2D;
+use thiserror::Error;
+
+use super::{
+    canvas::Anchor,
+    layers::{LayerCommand, LayerError},
+};
+
+/// Things to do that are typed out after a `:`, rather than bound to keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `:w [file]` saves to the file, or to whichever file was last saved or opened
+    Write(Option<PathBuf>),
+    /// `:e file` opens a file
+    Edit(PathBuf),
+    /// `:export file [scale]` writes a PNG, blowing each pixel up to `scale` pixels
+    Export { path: PathBuf, scale: u16 },
+    /// `:resize width height [anchor]` changes the size of the canvas. The anchor
+    /// is a compass direction, `nw` `n` `ne` `w` `c` `e` `sw` `s` or `se`, and
+    /// says which side keeps its cells, defaulting to `nw`.
+    Resize { size: Size2D<u16>, anchor: Anchor },
+    /// `:layer …` adds, removes, picks, moves or changes a layer
+    Layer(LayerCommand),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("not an editor command: {0}")]
+    Unknown(String),
+
+    #[error(":{0} needs a file name")]
+    MissingArgument(&'static str),
+
+    #[error("`{0}` is not a scale, it should be a whole number above 0")]
+    InvalidScale(String),
+
+    #[error(":resize needs a width and a height")]
+    MissingSize,
+
+    #[error("`{0}` is not a size, it should be a whole number above 0")]
+    InvalidSize(String),
+
+    #[error("{0}×{1} is too big, a canvas holds at most {max} cells", max = u16::MAX)]
+    TooLarge(u16, u16),
+
+    #[error("`{0}` is not an anchor, use one of {anchors}", anchors = ANCHORS.join(" "))]
+    InvalidAnchor(String),
+
+    #[error(
+        ":layer needs a layer number, or one of {commands}",
+        commands = LAYER_COMMANDS.join(" ")
+    )]
+    MissingLayerCommand,
+
+    #[error(
+        "`{0}` is not a layer command, use a layer number or one of {commands}",
+        commands = LAYER_COMMANDS.join(" ")
+    )]
+    InvalidLayerCommand(String),
+
+    #[error("`{0}` is not an opacity, it should be a percentage from 0 to 100")]
+    InvalidOpacity(String),
+
+    #[error(":{0} works on files, which there are none of here")]
+    NoFiles(&'static str),
+
+    #[error("{0}")]
+    Layer(LayerError),
+}
+
+/// Every command's full name
+pub const NAMES: &[&str] = &["edit", "export", "layer", "resize", "write"];
+
+const LAYER_COMMANDS: &[&str] = &[
+    "new", "delete", "up", "down", "merge", "show", "hide", "lock", "unlock", "opacity",
+];
+
+const ANCHORS: &[&str] = &["nw", "n", "ne", "w", "c", "e", "sw", "s", "se"];
+
+/// What a word after a command's name is, so it can be completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    Path,
+    /// One of a few words
+    Word(&'static [&'static str]),
+    /// Anything else, like a number
+    Other,
+}
+
+/// What the `index`th word after a command's name is
+pub fn argument(name: &str, index: usize) -> Argument {
+    match (name, index) {
+        ("w" | "write" | "e" | "edit" | "export", 0) => Argument::Path,
+        ("resize", 2) => Argument::Word(ANCHORS),
+        ("layer", 0) => Argument::Word(LAYER_COMMANDS),
+        _ => Argument::Other,
+    }
+}
+
+/// Parses a command line, without the leading `:`
+pub fn parse_command(line: &str) -> Result<Command, CommandError> {
+    let line = line.trim();
+    let (name, argument) = match line.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, Some(argument.trim())),
+        None => (line, None),
+    };
+    let path = argument.filter(|a| !a.is_empty()).map(PathBuf::from);
+
+    match name {
+        "w" | "write" => Ok(Command::Write(path)),
+        "e" | "edit" => path
+            .map(Command::Edit)
+            .ok_or(CommandError::MissingArgument("edit")),
+        "export" => {
+            let mut words = argument.unwrap_or_default().split_whitespace();
+            let path = words
+                .next()
+                .ok_or(CommandError::MissingArgument("export"))?;
+            let scale = match words.next() {
+                Some(word) => word
+                    .parse()
+                    .ok()
+                    .filter(|&scale| scale > 0)
+                    .ok_or_else(|| CommandError::InvalidScale(word.into()))?,
+                None => 1,
+            };
+            Ok(Command::Export {
+                path: path.into(),
+                scale,
+            })
+        }
+        "resize" => {
+            let mut words = argument.unwrap_or_default().split_whitespace();
+            let mut side = || {
+                let word = words.next().ok_or(CommandError::MissingSize)?;
+                word.parse()
+                    .ok()
+                    .filter(|&side| side > 0)
+                    .ok_or_else(|| CommandError::InvalidSize(word.into()))
+            };
+            let size: Size2D<u16> = (side()?, side()?).into();
+            if size.width as u32 * size.height as u32 > u16::MAX as u32 {
+                return Err(CommandError::TooLarge(size.width, size.height));
+            }
+
+            let anchor = match words.next() {
+                Some(word) => parse_anchor(word)?,
+                None => Anchor::default(),
+            };
+            Ok(Command::Resize { size, anchor })
+        }
+        "layer" => match argument.filter(|a| !a.is_empty()) {
+            Some(argument) => parse_layer_command(argument).map(Command::Layer),
+            None => Err(CommandError::MissingLayerCommand),
+        },
+        _ => Err(CommandError::Unknown(name.into())),
+    }
+}
+
+fn parse_layer_command(argument: &str) -> Result<LayerCommand, CommandError> {
+    let (word, rest) = match argument.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (argument, ""),
+    };
+    Ok(match word {
+        "new" => LayerCommand::New(Some(rest.to_string()).filter(|n| !n.is_empty())),
+        "delete" => LayerCommand::Delete,
+        "up" => LayerCommand::Raise,
+        "down" => LayerCommand::Lower,
+        "merge" => LayerCommand::Merge,
+        "show" => LayerCommand::Visible(true),
+        "hide" => LayerCommand::Visible(false),
+        "lock" => LayerCommand::Locked(true),
+        "unlock" => LayerCommand::Locked(false),
+        "opacity" => LayerCommand::Opacity(
+            rest.parse()
+                .ok()
+                .filter(|&o| o <= 100)
+                .ok_or_else(|| CommandError::InvalidOpacity(rest.into()))?,
+        ),
+        _ => LayerCommand::Select(
+            word.parse()
+                .map_err(|_| CommandError::InvalidLayerCommand(word.into()))?,
+        ),
+    })
+}
+
+fn parse_anchor(word: &str) -> Result<Anchor, CommandError> {
+    Ok(match word {
+        "nw" => Anchor::TopLeft,
+        "n" => Anchor::Top,
+        "ne" => Anchor::TopRight,
+        "w" => Anchor::Left,
+        "c" => Anchor::Center,
+        "e" => Anchor::Right,
+        "sw" => Anchor::BottomLeft,
+        "s" => Anchor::Bottom,
+        "se" => Anchor::BottomRight,
+        _ => return Err(CommandError::InvalidAnchor(word.into())),
+    })
+}
+
+#[cfg(test)]
+mod command_test {
+    use super::*;
+
+    #[test]
+    fn parsing_commands() {
+        assert_eq!(parse_command("w"), Ok(Command::Write(None)));
+        assert_eq!(
+            parse_command(" write  art.sadie "),
+            Ok(Command::Write(Some("art.sadie".into())))
+        );
+        assert_eq!(
+            parse_command("e art.sadie"),
+            Ok(Command::Edit("art.sadie".into()))
+        );
+        assert_eq!(
+            parse_command("e"),
+            Err(CommandError::MissingArgument("edit"))
+        );
+        assert_eq!(
+            parse_command("export art.png 4"),
+            Ok(Command::Export {
+                path: "art.png".into(),
+                scale: 4
+            })
+        );
+        assert_eq!(
+            parse_command("export art.png 0"),
+            Err(CommandError::InvalidScale("0".into()))
+        );
+        assert_eq!(
+            parse_command("resize 40 20 se"),
+            Ok(Command::Resize {
+                size: (40, 20).into(),
+                anchor: Anchor::BottomRight
+            })
+        );
+        assert_eq!(parse_command("resize 40"), Err(CommandError::MissingSize));
+        assert_eq!(
+            parse_command("resize 40 x"),
+            Err(CommandError::InvalidSize("x".into()))
+        );
+        assert_eq!(
+            parse_command("resize 300 300"),
+            Err(CommandError::TooLarge(300, 300))
+        );
+        assert_eq!(
+            parse_command("resize 4 4 up"),
+            Err(CommandError::InvalidAnchor("up".into()))
+        );
+        assert_eq!(
+            parse_command("layer new  ink and paper "),
+            Ok(Command::Layer(LayerCommand::New(Some(
+                "ink and paper".into()
+            ))))
+        );
+        assert_eq!(
+            parse_command("layer new"),
+            Ok(Command::Layer(LayerCommand::New(None)))
+        );
+        assert_eq!(
+            parse_command("layer 2"),
+            Ok(Command::Layer(LayerCommand::Select(2)))
+        );
+        assert_eq!(
+            parse_command("layer opacity 40"),
+            Ok(Command::Layer(LayerCommand::Opacity(40)))
+        );
+        assert_eq!(
+            parse_command("layer opacity 140"),
+            Err(CommandError::InvalidOpacity("140".into()))
+        );
+        assert_eq!(
+            parse_command("layer"),
+            Err(CommandError::MissingLayerCommand)
+        );
+        assert_eq!(
+            parse_command("wq!"),
+            Err(CommandError::Unknown("wq!".into()))
+        );
+    }
+}
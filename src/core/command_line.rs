@@ -0,0 +1,339 @@
+//! The line typed after `:`. It's edited apart from the action buffer, since
+//! what's typed is free text rather than keys that mean something.
+
+use std::fs;
+
+use super::{
+    actions::ActionError,
+    command::{self, parse_command, Argument, Command},
+    input::{Key, KeyEvent, Modifiers},
+};
+
+/// A line being typed, with vim's history and tab completion.
+///
+/// Up and down step through lines entered before that start with what's been
+/// typed. Tab completes the word before the cursor, whether it's a command's
+/// name, a file or one of the words a command takes, and pressing it again
+/// moves on to the next match.
+#[derive(Debug, Default)]
+pub struct CommandLine {
+    /// What's typed so far, or `None` when the line is closed
+    line: Option<String>,
+    /// Byte offset of the cursor in the line
+    cursor: usize,
+    /// Lines that were entered, oldest first
+    history: Vec<String>,
+    /// The history entry being shown, and what was typed before stepping back
+    browsing: Option<(usize, String)>,
+    completion: Option<Completion>,
+}
+
+/// Matches for the word being completed, and the one that's been filled in
+#[derive(Debug)]
+struct Completion {
+    start: usize,
+    matches: Vec<String>,
+    shown: usize,
+}
+
+impl CommandLine {
+    /// Lines further back than this are forgotten
+    pub const HISTORY: usize = 100;
+
+    pub fn open(&mut self) {
+        self.line = Some(String::new());
+        self.cursor = 0;
+        self.browsing = None;
+        self.completion = None;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.line.is_some()
+    }
+
+    /// What's typed so far, without the `:`
+    pub fn text(&self) -> &str {
+        self.line.as_deref().unwrap_or_default()
+    }
+
+    /// How many characters are before the cursor
+    pub fn cursor(&self) -> usize {
+        self.text()[..self.cursor].chars().count()
+    }
+
+    /// Edits the line with a key. Once enter is pressed the line closes and
+    /// the command it holds is returned.
+    pub fn input(&mut self, key: KeyEvent) -> Option<Result<Command, ActionError>> {
+        let line = self.line.as_mut()?;
+        let completing = key.key == Key::Tab;
+        match (key.key, key.modifiers) {
+            (Key::Enter, _) => return Some(self.enter()),
+            (Key::Escape, _) | (Key::Char('c'), Modifiers::CTRL) => self.line = None,
+            // backspacing past the start gives up on the line, like vim
+            (Key::Backspace, _) if line.is_empty() => self.line = None,
+            (Key::Backspace, _) => {
+                if let Some(c) = line[..self.cursor].chars().next_back() {
+                    self.cursor -= c.len_utf8();
+                    line.remove(self.cursor);
+                }
+            }
+            (Key::Char('u'), Modifiers::CTRL) => {
+                line.replace_range(..self.cursor, "");
+                self.cursor = 0;
+            }
+            (Key::Left, _) => {
+                let c = line[..self.cursor].chars().next_back();
+                self.cursor -= c.map_or(0, char::len_utf8);
+            }
+            (Key::Right, _) => {
+                let c = line[self.cursor..].chars().next();
+                self.cursor += c.map_or(0, char::len_utf8);
+            }
+            (Key::Up, _) => self.browse(-1),
+            (Key::Down, _) => self.browse(1),
+            (Key::Tab, m) => self.complete(if m.shift { -1 } else { 1 }),
+            (Key::Char(c), m) if !m.ctrl && !m.alt => {
+                line.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
+            _ => {}
+        }
+        if !completing {
+            self.completion = None;
+        }
+        None
+    }
+
+    fn enter(&mut self) -> Result<Command, ActionError> {
+        let line = self.line.take().unwrap_or_default();
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+            if self.history.len() > Self::HISTORY {
+                self.history.remove(0);
+            }
+        }
+
+        parse_command(&line).map_err(|error| ActionError::BadCommand {
+            error,
+            buffer: format!(":{line}"),
+            span: 1..1 + line.len(),
+        })
+    }
+
+    /// Shows an older or newer line from the history that starts with what was
+    /// typed, or what was typed when stepping past the newest one
+    fn browse(&mut self, step: isize) {
+        let Some(line) = self.line.as_mut() else {
+            return;
+        };
+        let newest = self.history.len();
+        let (mut index, typed) = self
+            .browsing
+            .take()
+            .unwrap_or_else(|| (newest, line.clone()));
+
+        let mut next = index;
+        while let Some(i) = next.checked_add_signed(step).filter(|&i| i <= newest) {
+            next = i;
+            if i == newest || self.history[i].starts_with(&typed) {
+                index = i;
+                break;
+            }
+        }
+
+        if index == newest {
+            *line = typed;
+        } else {
+            *line = self.history[index].clone();
+            self.browsing = Some((index, typed));
+        }
+        self.cursor = line.len();
+    }
+
+    /// Fills in the next or previous match for the word before the cursor
+    fn complete(&mut self, step: isize) {
+        let Some(line) = self.line.as_mut() else {
+            return;
+        };
+        let completion = match self.completion.as_mut() {
+            Some(c) => {
+                let count = c.matches.len() as isize;
+                c.shown = (c.shown as isize + step).rem_euclid(count) as usize;
+                c
+            }
+            None => {
+                let (start, matches) = completions(&line[..self.cursor]);
+                if matches.is_empty() {
+                    return;
+                }
+                let shown = if step < 0 { matches.len() - 1 } else { 0 };
+                self.completion.insert(Completion {
+                    start,
+                    matches,
+                    shown,
+                })
+            }
+        };
+
+        let word = &completion.matches[completion.shown];
+        line.replace_range(completion.start..self.cursor, word);
+        self.cursor = completion.start + word.len();
+    }
+}
+
+/// Where the word being typed starts, and what it could be completed to
+fn completions(before: &str) -> (usize, Vec<String>) {
+    let start = before.rfind(' ').map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let mut words = before[..start].split_whitespace();
+
+    let matches = match words.next() {
+        None => command::NAMES
+            .iter()
+            .filter(|n| n.starts_with(word))
+            .map(|n| n.to_string())
+            .collect(),
+        Some(name) => match command::argument(name, words.count()) {
+            Argument::Path => paths(word),
+            Argument::Word(choices) => choices
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| c.to_string())
+                .collect(),
+            Argument::Other => vec![],
+        },
+    };
+    (start, matches)
+}
+
+/// Files and directories that start with a partly typed path. Hidden ones are
+/// left out unless the name being typed starts with a dot.
+fn paths(typed: &str) -> Vec<String> {
+    let (dir, prefix) = match typed.rfind('/') {
+        Some(i) => typed.split_at(i + 1),
+        None => ("", typed),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return vec![];
+    };
+
+    let mut found: Vec<String> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.file_type().ok()?.is_dir() {
+                "/"
+            } else {
+                ""
+            };
+            Some(format!("{dir}{name}{slash}"))
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+#[cfg(test)]
+mod command_line_test {
+    use super::*;
+    use crate::core::{command::CommandError, input::parse_keys};
+
+    fn typed(line: &mut CommandLine, keys: &str) -> Option<Result<Command, ActionError>> {
+        if !line.is_open() {
+            line.open();
+        }
+        parse_keys(keys).into_iter().find_map(|k| line.input(k))
+    }
+
+    #[test]
+    fn editing() {
+        let mut line = CommandLine::default();
+        assert_eq!(
+            typed(&mut line, "w art<Left><Left><BS>X<CR>"),
+            Some(Ok(Command::Write(Some("Xrt".into()))))
+        );
+        assert!(!line.is_open());
+
+        assert_eq!(
+            typed(&mut line, "w <lt>3.sadie<CR>"),
+            Some(Ok(Command::Write(Some("<3.sadie".into()))))
+        );
+        assert_eq!(
+            typed(&mut line, "e<CR>"),
+            Some(Err(ActionError::BadCommand {
+                error: CommandError::MissingArgument("edit"),
+                buffer: ":e".into(),
+                span: 1..2
+            }))
+        );
+
+        assert_eq!(typed(&mut line, "wq<Esc>"), None);
+        assert!(!line.is_open());
+        assert_eq!(typed(&mut line, "w<BS><BS>"), None);
+        assert!(!line.is_open());
+        typed(&mut line, "resize 4<C-u>w");
+        assert_eq!((line.text(), line.cursor()), ("w", 1));
+    }
+
+    #[test]
+    fn history() {
+        let mut line = CommandLine::default();
+        typed(&mut line, "w one<CR>");
+        typed(&mut line, "e two<CR>");
+        typed(&mut line, "w three<CR>");
+
+        typed(&mut line, "<Up>");
+        assert_eq!(line.text(), "w three");
+        typed(&mut line, "<Up><Up><Up>");
+        assert_eq!(line.text(), "w one");
+        typed(&mut line, "<Down><Down><Down>");
+        assert_eq!(line.text(), "");
+        line.input(Key::Escape.into());
+
+        // only lines starting with what's typed are stepped through
+        typed(&mut line, "w<Up>");
+        assert_eq!(line.text(), "w three");
+        typed(&mut line, "<Up>");
+        assert_eq!(line.text(), "w one");
+        typed(&mut line, "<Down><Down>");
+        assert_eq!(line.text(), "w");
+    }
+
+    #[test]
+    fn completing() {
+        let mut line = CommandLine::default();
+        typed(&mut line, "e<Tab>");
+        assert_eq!(line.text(), "edit");
+        typed(&mut line, "<Tab>");
+        assert_eq!(line.text(), "export");
+        typed(&mut line, "<S-Tab><S-Tab>");
+        assert_eq!(line.text(), "export");
+        line.input(Key::Escape.into());
+
+        typed(&mut line, "layer lo<Tab>");
+        assert_eq!(line.text(), "layer lock");
+        line.input(Key::Escape.into());
+        typed(&mut line, "resize 4 4 s<Tab><Tab><Tab><Tab>");
+        assert_eq!(line.text(), "resize 4 4 sw");
+        line.input(Key::Escape.into());
+
+        let dir = std::env::temp_dir().join(format!("sadie-completion-{}", std::process::id()));
+        fs::create_dir_all(dir.join("art")).unwrap();
+        fs::write(dir.join("apple.sadie"), []).unwrap();
+        fs::write(dir.join(".hidden"), []).unwrap();
+        let dir = dir.display().to_string();
+
+        typed(&mut line, &format!("w {dir}/a<Tab>"));
+        assert_eq!(line.text(), format!("w {dir}/apple.sadie"));
+        typed(&mut line, "<Tab>");
+        assert_eq!(line.text(), format!("w {dir}/art/"));
+        line.input(Key::Escape.into());
+        assert_eq!(paths(&format!("{dir}/")).len(), 2);
+        assert_eq!(paths(&format!("{dir}/.")).len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+
+use super::{
+    layers::{Layer, LayerError},
+    CanvasPos, CharID,
+};
+
+/// A cell that was overwritten, and what it was overwritten with
+struct CellChange<A> {
+    layer: usize,
+    position: CanvasPos,
+    before: (CharID, A),
+    after: (CharID, A),
+}
+
+/// Changes that are undone and redone together
+struct Transaction<A>(Vec<CellChange<A>>);
+
+impl<A> Transaction<A> {
+    fn first_position(&self) -> Option<CanvasPos> {
+        self.0.first().map(|c| c.position)
+    }
+
+    /// Locked layers can't be changed by undoing or redoing either
+    fn check_unlocked(&self, layers: &[Layer<A>]) -> Result<(), LayerError> {
+        match self.0.iter().map(|c| &layers[c.layer]).find(|l| l.locked) {
+            Some(layer) => Err(LayerError::Locked(layer.name.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Keeps track of edits made to a canvas so they can be reverted.
+///
+/// Changes are recorded as they are made and grouped into a transaction when
+/// they are committed. Once more than `capacity` cell changes are stored, the
+/// oldest transactions are forgotten.
+pub struct History<A> {
+    undo: VecDeque<Transaction<A>>,
+    redo: Vec<Transaction<A>>,
+    pending: Vec<CellChange<A>>,
+    capacity: usize,
+    /// Number of cell changes held by every transaction
+    stored: usize,
+}
+
+impl<A> History<A> {
+    pub const DEFAULT_CAPACITY: usize = 1 << 16;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: vec![],
+            pending: vec![],
+            capacity,
+            stored: 0,
+        }
+    }
+
+    /// Remembers that a cell of a layer is about to change
+    pub fn record(
+        &mut self,
+        layer: usize,
+        position: CanvasPos,
+        before: (CharID, A),
+        after: (CharID, A),
+    ) {
+        self.pending.push(CellChange {
+            layer,
+            position,
+            before,
+            after,
+        });
+    }
+
+    /// Forgets every edit, for when the cells they were made to no longer exist
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.pending.clear();
+        self.stored = 0;
+    }
+
+    /// Groups every change recorded since the last commit into one undo step
+    pub fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        self.stored += self.pending.len();
+        self.undo
+            .push_back(Transaction(std::mem::take(&mut self.pending)));
+
+        self.stored -= self.redo.iter().map(|t| t.0.len()).sum::<usize>();
+        self.redo.clear();
+
+        // always keep the newest step, even when it's bigger than the capacity
+        while self.stored > self.capacity && self.undo.len() > 1 {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.stored -= oldest.0.len();
+            }
+        }
+    }
+}
+
+impl<A: Clone> History<A> {
+    /// Reverts the latest step, returning where the first change was
+    pub(crate) fn undo(
+        &mut self,
+        layers: &mut [Layer<A>],
+    ) -> Result<Option<CanvasPos>, LayerError> {
+        let Some(transaction) = self.undo.back() else {
+            return Ok(None);
+        };
+        transaction.check_unlocked(layers)?;
+        let transaction = self.undo.pop_back().expect("checked above");
+        for change in transaction.0.iter().rev() {
+            let p = change.position;
+            layers[change.layer].grid_mut()[[p.x, p.y]] = change.before.clone();
+        }
+        let position = transaction.first_position();
+        self.redo.push(transaction);
+        Ok(position)
+    }
+
+    /// Reapplies the latest undone step, returning where the first change was
+    pub(crate) fn redo(
+        &mut self,
+        layers: &mut [Layer<A>],
+    ) -> Result<Option<CanvasPos>, LayerError> {
+        let Some(transaction) = self.redo.last() else {
+            return Ok(None);
+        };
+        transaction.check_unlocked(layers)?;
+        let transaction = self.redo.pop().expect("checked above");
+        for change in transaction.0.iter() {
+            let p = change.position;
+            layers[change.layer].grid_mut()[[p.x, p.y]] = change.after.clone();
+        }
+        let position = transaction.first_position();
+        self.undo.push_back(transaction);
+        Ok(position)
+    }
+}
+
+impl<A> Default for History<A> {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
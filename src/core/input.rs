@@ -0,0 +1,235 @@
+use std::fmt;
+
+use euclid::default::Point2D;
+
+/// Something the user did, as reported by a frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A key that types a character, shift is already applied to it
+    Char(char),
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    /// Only kept for keys that don't type characters
+    pub shift: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Self = Self {
+        ctrl: false,
+        alt: false,
+        shift: false,
+    };
+    pub const CTRL: Self = Self {
+        ctrl: true,
+        ..Self::NONE
+    };
+}
+
+/// A key press, written out in vim's `<C-r>` style notation when displayed.
+/// This is how keys are kept in the action buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    pub fn new(key: Key, modifiers: Modifiers) -> Self {
+        let shift = modifiers.shift && !matches!(key, Key::Char(_));
+        Self {
+            key,
+            modifiers: Modifiers { shift, ..modifiers },
+        }
+    }
+}
+
+impl From<char> for KeyEvent {
+    fn from(c: char) -> Self {
+        Self::new(Key::Char(c), Modifiers::NONE)
+    }
+}
+
+impl From<Key> for KeyEvent {
+    fn from(key: Key) -> Self {
+        Self::new(key, Modifiers::NONE)
+    }
+}
+
+impl fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.key {
+            Key::Char('<') => "lt".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Escape => "Esc".into(),
+            Key::Enter => "CR".into(),
+            Key::Tab => "Tab".into(),
+            Key::Backspace => "BS".into(),
+            Key::Up => "Up".into(),
+            Key::Down => "Down".into(),
+            Key::Left => "Left".into(),
+            Key::Right => "Right".into(),
+        };
+
+        let m = self.modifiers;
+        if let (Key::Char(c), Modifiers::NONE) = (self.key, m) {
+            if c != '<' {
+                return write!(f, "{c}");
+            }
+        }
+
+        write!(f, "<")?;
+        for (held, prefix) in [(m.ctrl, "C-"), (m.alt, "A-"), (m.shift, "S-")] {
+            if held {
+                write!(f, "{prefix}")?;
+            }
+        }
+        write!(f, "{name}>")
+    }
+}
+
+/// Reads keys written in the notation they're displayed in, e.g. `3j<C-r>`.
+/// A `<` that doesn't start a key name is taken as itself.
+pub fn parse_keys(notation: &str) -> Vec<KeyEvent> {
+    let mut keys = vec![];
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        let named = (c == '<')
+            .then(|| rest.find('>'))
+            .flatten()
+            .and_then(|end| Some((parse_key_name(&rest[1..end])?, end + 1)));
+
+        match named {
+            Some((key, length)) => {
+                keys.push(key);
+                rest = &rest[length..];
+            }
+            None => {
+                keys.push(c.into());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    keys
+}
+
+/// The part between the angle brackets, like `C-r` or `Esc`
+fn parse_key_name(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = Modifiers::NONE;
+    let mut name = name;
+    while let Some((prefix, rest)) = name.split_once('-').filter(|(_, rest)| !rest.is_empty()) {
+        match prefix {
+            "C" => modifiers.ctrl = true,
+            "A" => modifiers.alt = true,
+            "S" => modifiers.shift = true,
+            _ => return None,
+        }
+        name = rest;
+    }
+
+    let key = match name {
+        "lt" => Key::Char('<'),
+        "Space" => Key::Char(' '),
+        "Esc" => Key::Escape,
+        "CR" => Key::Enter,
+        "Tab" => Key::Tab,
+        "BS" => Key::Backspace,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Key::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(KeyEvent::new(key, modifiers))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    /// Moved while the button is held down
+    Drag(MouseButton),
+    Release(MouseButton),
+    /// Lines scrolled, positive is away from the user
+    Scroll(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    /// Where the pointer is, in pixels for a window and in cells for a terminal
+    pub position: Point2D<i32>,
+}
+
+#[cfg(test)]
+mod input_test {
+    use super::*;
+
+    #[test]
+    fn notation() {
+        let ctrl_r = KeyEvent::new(Key::Char('r'), Modifiers::CTRL);
+        assert_eq!(ctrl_r.to_string(), "<C-r>");
+        assert_eq!(KeyEvent::from('G').to_string(), "G");
+        assert_eq!(KeyEvent::from('<').to_string(), "<lt>");
+        assert_eq!(KeyEvent::from(Key::Escape).to_string(), "<Esc>");
+
+        let shift = Modifiers {
+            shift: true,
+            ..Modifiers::NONE
+        };
+        assert_eq!(KeyEvent::new(Key::Tab, shift).to_string(), "<S-Tab>");
+        assert_eq!(KeyEvent::new(Key::Char('G'), shift).to_string(), "G");
+    }
+
+    #[test]
+    fn parsing_keys() {
+        assert_eq!(
+            parse_keys("3j<C-r><Up>"),
+            vec![
+                '3'.into(),
+                'j'.into(),
+                KeyEvent::new(Key::Char('r'), Modifiers::CTRL),
+                Key::Up.into()
+            ]
+        );
+        assert_eq!(
+            parse_keys("<lt><a"),
+            vec!['<'.into(), '<'.into(), 'a'.into()]
+        );
+        assert_eq!(parse_keys("<Nope>"), parse_keys("<lt>Nope>"));
+        assert_eq!(parse_keys("<Space>"), parse_keys(" "));
+
+        let keys = "2d<S-Tab><C-A-x><C--><CR>";
+        let typed: String = parse_keys(keys).iter().map(|k| k.to_string()).collect();
+        assert_eq!(typed, keys);
+    }
+}
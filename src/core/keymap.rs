@@ -0,0 +1,221 @@
+//! Rebinding keys from a TOML file, for layouts other than QWERTY or for
+//! anyone who'd rather keep their hand elsewhere. A file looks like:
+//!
+//! ```toml
+//! [normal]
+//! n = "left"
+//! e = "down"
+//! "<C-e>" = "redo"
+//!
+//! [visual]
+//! x = "delete"
+//! ```
+//!
+//! Each table maps keys, written like the `--script` flag takes them, to the
+//! name of what they stand for (see [`BINDABLE`]). Visual mode starts from
+//! normal mode's bindings. Keys that aren't bound keep their usual meaning, and
+//! the key after `"`, `f`, `s`, `q` or `@` is always read as typed.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{
+    actions::{Mode, BINDABLE},
+    input::{parse_keys, KeyEvent},
+};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum KeymapError {
+    #[error("{0}")]
+    Toml(String),
+
+    #[error("there's no `[{0}]` mode, keymaps have `[normal]` and `[visual]`")]
+    UnknownMode(String),
+
+    #[error("`{0}` should be a table of keys")]
+    NotATable(String),
+
+    #[error("`{0}` should be bound to the name of an action, in quotes")]
+    NotAName(String),
+
+    #[error("`{keys}` is bound to `{name}`, which isn't something keys can do")]
+    UnknownAction { keys: String, name: String },
+
+    #[error("a binding in `[{0}]` has no keys")]
+    NoKeys(&'static str),
+
+    #[error("`{first}` and `{second}` are both bound in `[{mode}]`, but one starts the other")]
+    Conflict {
+        mode: &'static str,
+        first: String,
+        second: String,
+    },
+}
+
+/// What a sequence of keys means in a keymap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    /// The keys are bound, and stand for these
+    Bound(&'static str),
+    /// The keys start a longer binding, so more are needed
+    Prefix,
+    Unbound,
+}
+
+/// Keys that stand for others, in each mode
+#[derive(Debug, Default, Clone)]
+pub struct Keymap {
+    modes: HashMap<Mode, Vec<(Vec<KeyEvent>, &'static str)>>,
+}
+
+impl Keymap {
+    pub fn parse(text: &str) -> Result<Self, KeymapError> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| KeymapError::Toml(e.message().into()))?;
+
+        let mut tables = HashMap::new();
+        for (mode, bindings) in &table {
+            let mode = match mode.as_str() {
+                "normal" => Mode::Normal,
+                "visual" => Mode::Visual,
+                _ => return Err(KeymapError::UnknownMode(mode.clone())),
+            };
+            let bindings = bindings
+                .as_table()
+                .ok_or_else(|| KeymapError::NotATable(name(mode).into()))?;
+            tables.insert(mode, read_bindings(mode, bindings)?);
+        }
+
+        let normal = tables.remove(&Mode::Normal).unwrap_or_default();
+        let mut visual = normal.clone();
+        for (keys, stands_for) in tables.remove(&Mode::Visual).unwrap_or_default() {
+            visual.retain(|(k, _)| *k != keys);
+            visual.push((keys, stands_for));
+        }
+
+        check_conflicts(Mode::Normal, &normal)?;
+        check_conflicts(Mode::Visual, &visual)?;
+        Ok(Self {
+            modes: HashMap::from([(Mode::Normal, normal), (Mode::Visual, visual)]),
+        })
+    }
+
+    /// What keys typed one after another mean in a mode
+    pub fn lookup(&self, mode: Mode, keys: &[KeyEvent]) -> Lookup {
+        let bindings = self.modes.get(&mode).map_or(&[][..], Vec::as_slice);
+        if let Some((_, stands_for)) = bindings.iter().find(|(k, _)| k == keys) {
+            return Lookup::Bound(stands_for);
+        }
+        match bindings.iter().any(|(k, _)| k.starts_with(keys)) {
+            true => Lookup::Prefix,
+            false => Lookup::Unbound,
+        }
+    }
+}
+
+fn name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Normal => "normal",
+        Mode::Visual => "visual",
+    }
+}
+
+fn read_bindings(
+    mode: Mode,
+    table: &toml::Table,
+) -> Result<Vec<(Vec<KeyEvent>, &'static str)>, KeymapError> {
+    table
+        .iter()
+        .map(|(keys, action)| {
+            let action = action
+                .as_str()
+                .ok_or_else(|| KeymapError::NotAName(keys.clone()))?;
+            let (_, stands_for) = BINDABLE.iter().find(|(n, _)| *n == action).ok_or_else(|| {
+                KeymapError::UnknownAction {
+                    keys: keys.clone(),
+                    name: action.into(),
+                }
+            })?;
+            let typed = parse_keys(keys);
+            if typed.is_empty() {
+                return Err(KeymapError::NoKeys(name(mode)));
+            }
+            Ok((typed, *stands_for))
+        })
+        .collect()
+}
+
+/// Bindings can't be the start of another, or it would never be known which
+/// was meant. That includes the same keys written two ways, like `<lt>` and `<`.
+fn check_conflicts(mode: Mode, bindings: &[(Vec<KeyEvent>, &str)]) -> Result<(), KeymapError> {
+    for (i, (first, _)) in bindings.iter().enumerate() {
+        for (second, _) in &bindings[i + 1..] {
+            if first.starts_with(second) || second.starts_with(first) {
+                let write = |keys: &[KeyEvent]| keys.iter().map(|k| k.to_string()).collect();
+                return Err(KeymapError::Conflict {
+                    mode: name(mode),
+                    first: write(first),
+                    second: write(second),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod keymap_test {
+    use super::*;
+
+    #[test]
+    fn looking_up() {
+        let keymap = Keymap::parse(
+            r#"
+            [normal]
+            n = "left"
+            "<Space>d" = "delete"
+
+            [visual]
+            n = "right"
+            "#,
+        )
+        .unwrap();
+
+        let keys = parse_keys;
+        assert_eq!(keymap.lookup(Mode::Normal, &keys("n")), Lookup::Bound("h"));
+        assert_eq!(keymap.lookup(Mode::Visual, &keys("n")), Lookup::Bound("l"));
+        assert_eq!(keymap.lookup(Mode::Visual, &keys(" ")), Lookup::Prefix);
+        assert_eq!(keymap.lookup(Mode::Visual, &keys(" d")), Lookup::Bound("d"));
+        assert_eq!(keymap.lookup(Mode::Normal, &keys("h")), Lookup::Unbound);
+    }
+
+    #[test]
+    fn bad_keymaps() {
+        let error = |text| Keymap::parse(text).unwrap_err();
+        assert!(matches!(error("[normal"), KeymapError::Toml(_)));
+        assert_eq!(error("[insert]"), KeymapError::UnknownMode("insert".into()));
+        assert_eq!(error("normal = 3"), KeymapError::NotATable("normal".into()));
+        assert_eq!(error("[normal]\nn = 3"), KeymapError::NotAName("n".into()));
+        assert_eq!(
+            error("[normal]\nn = \"jump\""),
+            KeymapError::UnknownAction {
+                keys: "n".into(),
+                name: "jump".into()
+            }
+        );
+        assert_eq!(
+            error("[visual]\n\"\" = \"left\""),
+            KeymapError::NoKeys("visual")
+        );
+        assert_eq!(
+            error("[normal]\nn = \"left\"\nnn = \"right\"").to_string(),
+            "`n` and `nn` are both bound in `[normal]`, but one starts the other"
+        );
+        assert!(matches!(
+            error("[normal]\n\"<\" = \"left\"\n\"<lt>\" = \"right\""),
+            KeymapError::Conflict { .. }
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+//! Stacks of cell grids that are drawn over each other, like the layers of
+//! an image editor. Every layer of a canvas has the same size and charset.
+
+use euclid::default::{Size2D, Vector2D};
+use thiserror::Error;
+
+use super::{array2d::Array2D, CharID};
+
+/// Attributes that can be mixed, so a layer that isn't fully opaque lets the
+/// one under it show through
+pub trait Blend {
+    /// Mixes `self` over `below`, where an opacity of 1 is only `self`
+    fn blend(&self, below: &Self, opacity: f32) -> Self;
+}
+
+impl Blend for () {
+    fn blend(&self, _below: &Self, _opacity: f32) -> Self {}
+}
+
+/// Whether a cell lets the layers under it show through, which is the case for
+/// cells that were never drawn on or have been cleared
+pub fn is_transparent<A: Default + PartialEq>(cell: &(CharID, A)) -> bool {
+    cell.0 == 0 && cell.1 == A::default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer<A> {
+    pub name: String,
+    pub visible: bool,
+    /// Locked layers ignore edits
+    pub locked: bool,
+    /// How much the layer covers the ones under it, as a percentage
+    pub opacity: u8,
+    cells: Array2D<(CharID, A)>,
+}
+
+impl<A> Layer<A> {
+    /// Makes a visible, unlocked and opaque layer out of cells laid out row by row
+    pub fn new(name: impl Into<String>, cells: Vec<(CharID, A)>, width: u16) -> Self {
+        Self::from_grid(name, (cells, width).into())
+    }
+
+    pub(crate) fn from_grid(name: impl Into<String>, cells: Array2D<(CharID, A)>) -> Self {
+        Self {
+            name: name.into(),
+            visible: true,
+            locked: false,
+            opacity: 100,
+            cells,
+        }
+    }
+
+    /// Every cell, row by row
+    pub fn cells(&self) -> &[(CharID, A)] {
+        self.cells.slice()
+    }
+
+    pub(crate) fn grid(&self) -> &Array2D<(CharID, A)> {
+        &self.cells
+    }
+
+    pub(crate) fn grid_mut(&mut self) -> &mut Array2D<(CharID, A)> {
+        &mut self.cells
+    }
+
+    /// Whether the layer adds anything to the ones under it
+    pub fn shows(&self) -> bool {
+        self.visible && self.opacity > 0
+    }
+}
+
+impl<A: Default> Layer<A> {
+    /// A layer where every cell is transparent
+    pub fn blank(name: impl Into<String>, size: Size2D<u16>) -> Self {
+        let cells = (0..size.area()).map(|_| Default::default()).collect();
+        Self::new(name, cells, size.width)
+    }
+}
+
+impl<A: Clone + Default> Layer<A> {
+    pub(crate) fn resize(&mut self, size: Size2D<u16>, offset: Vector2D<i32>, fill: (CharID, A)) {
+        self.cells.resize(size, offset, fill);
+    }
+}
+
+/// Lays `above` over `below`. Glyphs can't be partly see-through, so the upper
+/// glyph wins unless its cell is transparent, while the attributes are mixed.
+pub fn composite<A>(below: &(CharID, A), above: &(CharID, A), opacity: u8) -> (CharID, A)
+where
+    A: Blend + Clone + Default + PartialEq,
+{
+    if is_transparent(above) {
+        return below.clone();
+    }
+    let opacity = opacity.min(100) as f32 / 100.;
+    (above.0, above.1.blend(&below.1, opacity))
+}
+
+/// Changes to the stack of layers, typed as `:layer …`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayerCommand {
+    /// `:layer new [name]` puts a blank layer above the active one and makes it active
+    New(Option<String>),
+    /// `:layer delete` removes the active layer
+    Delete,
+    /// `:layer 2` makes the second layer from the bottom active
+    Select(usize),
+    /// `:layer up` and `:layer down` move the active layer through the stack
+    Raise,
+    Lower,
+    /// `:layer merge` flattens the active layer into the one under it
+    Merge,
+    /// `:layer show`, `:layer hide`, `:layer lock` and `:layer unlock`
+    Visible(bool),
+    Locked(bool),
+    /// `:layer opacity 50` sets how much of the active layer shows, in percent
+    Opacity(u8),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LayerError {
+    #[error("can't delete the only layer")]
+    LastLayer,
+
+    #[error("there's no layer {0}, the canvas has {1}")]
+    NoSuchLayer(usize, usize),
+
+    #[error("there's no layer under this one to merge into")]
+    NothingBelow,
+
+    #[error("layer {0} is locked")]
+    Locked(String),
+}
+
+#[cfg(test)]
+mod layers_test {
+    use super::*;
+
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    struct Shade(f32);
+
+    impl Blend for Shade {
+        fn blend(&self, below: &Self, opacity: f32) -> Self {
+            Shade(below.0 + (self.0 - below.0) * opacity)
+        }
+    }
+
+    #[test]
+    fn compositing() {
+        let below = (3, Shade(1.));
+        assert_eq!(composite(&below, &(0, Shade(0.)), 100), below);
+        assert_eq!(composite(&below, &(5, Shade(0.)), 100), (5, Shade(0.)));
+        assert_eq!(composite(&below, &(0, Shade(0.5)), 50), (0, Shade(0.75)));
+    }
+}
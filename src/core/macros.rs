@@ -0,0 +1,94 @@
+//! Keys recorded with `q` and typed again with `@`.
+
+use std::collections::HashMap;
+
+use super::input::KeyEvent;
+
+/// Keys recorded into named registers. These are kept apart from the registers
+/// cells are yanked into, which hold blocks rather than keys.
+#[derive(Debug, Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<KeyEvent>>,
+    /// The register being recorded into, and the keys typed since
+    recording: Option<(char, Vec<KeyEvent>)>,
+    /// What `@@` plays
+    last_played: Option<char>,
+}
+
+impl Macros {
+    /// Playing more keys than this between two typed ones gives up, since the
+    /// macro most likely plays itself
+    pub const MAX_KEYS: usize = 100_000;
+
+    pub fn record(&mut self, register: char) {
+        self.recording = Some((register, vec![]));
+    }
+
+    /// The register being recorded into
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    /// Keeps a typed key, if recording
+    pub fn push(&mut self, key: KeyEvent) {
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
+        }
+    }
+
+    /// Stops recording, replacing what the register held
+    pub fn stop(&mut self) {
+        if let Some((register, keys)) = self.recording.take() {
+            self.registers.insert(register, keys);
+        }
+    }
+
+    /// The keys in a register, `count` times over. `None` plays the register
+    /// that was played last. Gives `None` when that's more than `limit` keys.
+    pub fn play(
+        &mut self,
+        register: Option<char>,
+        count: u16,
+        limit: usize,
+    ) -> Option<Vec<KeyEvent>> {
+        let Some(register) = register.or(self.last_played) else {
+            return Some(vec![]);
+        };
+        self.last_played = Some(register);
+
+        let keys = self.registers.get(&register).map_or(&[][..], Vec::as_slice);
+        if keys.len().saturating_mul(count as usize) > limit {
+            return None;
+        }
+        Some(keys.repeat(count as usize))
+    }
+}
+
+#[cfg(test)]
+mod macros_test {
+    use super::*;
+    use crate::core::input::parse_keys;
+
+    #[test]
+    fn recording() {
+        let mut macros = Macros::default();
+        macros.push('x'.into());
+        macros.record('a');
+        assert_eq!(macros.recording(), Some('a'));
+        parse_keys("dl<C-r>")
+            .into_iter()
+            .for_each(|k| macros.push(k));
+        macros.stop();
+        assert_eq!(macros.recording(), None);
+
+        let limit = Macros::MAX_KEYS;
+        assert_eq!(macros.play(None, 1, limit), Some(vec![]));
+        assert_eq!(
+            macros.play(Some('a'), 2, limit),
+            Some(parse_keys("dl<C-r>dl<C-r>"))
+        );
+        assert_eq!(macros.play(None, 1, limit), Some(parse_keys("dl<C-r>")));
+        assert_eq!(macros.play(None, u16::MAX, limit), None);
+        assert_eq!(macros.play(Some('b'), 1, limit), Some(vec![]));
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use euclid::default::Size2D;
+
+use super::CharID;
+
+/// A rectangle of cells copied out of a canvas
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block<A> {
+    size: Size2D<u16>,
+    /// Row by row, like the canvas they came from
+    cells: Vec<(CharID, A)>,
+}
+
+impl<A> Block<A> {
+    /// Makes a block out of cells laid out row by row, there must be one for
+    /// every cell of `size`
+    pub fn new(size: Size2D<u16>, cells: Vec<(CharID, A)>) -> Self {
+        assert_eq!(cells.len(), size.area() as usize, "block is the wrong size");
+        Self { size, cells }
+    }
+
+    pub fn size(&self) -> Size2D<u16> {
+        self.size
+    }
+
+    pub fn get(&self, x: u16, y: u16) -> &(CharID, A) {
+        &self.cells[y as usize * self.size.width as usize + x as usize]
+    }
+}
+
+/// Where yanked and deleted blocks are kept for putting back later.
+///
+/// Like vim, `"a` to `"z` name the register the next yank, delete or put uses.
+/// Without one the unnamed register is used, which also gets a copy of
+/// everything stored into a named register.
+pub struct Registers<A> {
+    unnamed: Option<Block<A>>,
+    named: HashMap<char, Block<A>>,
+    /// Named by the last `"x`, and not used up yet
+    selected: Option<char>,
+}
+
+impl<A: Clone> Registers<A> {
+    pub fn select(&mut self, name: char) {
+        self.selected = Some(name);
+    }
+
+    pub fn store(&mut self, block: Block<A>) {
+        if let Some(name) = self.selected.take() {
+            self.named.insert(name, block.clone());
+        }
+        self.unnamed = Some(block);
+    }
+
+    /// The block the next put uses, which uses up the selected name
+    pub fn take(&mut self) -> Option<&Block<A>> {
+        match self.selected.take() {
+            Some(name) => self.named.get(&name),
+            None => self.unnamed.as_ref(),
+        }
+    }
+}
+
+impl<A> Default for Registers<A> {
+    fn default() -> Self {
+        Self {
+            unnamed: None,
+            named: HashMap::new(),
+            selected: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod registers_test {
+    use super::*;
+
+    fn block(id: CharID) -> Block<()> {
+        Block::new((1, 1).into(), vec![(id, ())])
+    }
+
+    #[test]
+    fn naming_registers() {
+        let mut registers = Registers::default();
+        assert_eq!(registers.take(), None);
+
+        registers.select('a');
+        registers.store(block(1));
+        registers.store(block(2));
+        assert_eq!(registers.take(), Some(&block(2)));
+
+        registers.select('a');
+        assert_eq!(registers.take(), Some(&block(1)));
+        registers.select('b');
+        assert_eq!(registers.take(), None);
+    }
+}
@@ -0,0 +1,75 @@
+//! Remembering the last change made to a canvas, so `.` can make it again.
+
+use euclid::default::Vector2D;
+
+use super::actions::{Action, Motion};
+
+/// A change as it was made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<B> {
+    pub action: Action,
+    /// Where the selection it was made on started, relative to the cursor
+    pub selection: Option<Vector2D<i32>>,
+    /// Whatever it was made with that comes from outside the canvas, like the brush
+    pub brush: B,
+}
+
+/// The last change, which `.` makes again at the cursor
+#[derive(Debug, Clone, Default)]
+pub struct LastChange<B>(Option<Change<B>>);
+
+impl<B: Clone> LastChange<B> {
+    /// Keeps an action if it changes cells, along with the selection it's made
+    /// on, which is otherwise used up by the time it could be asked for
+    pub fn record(&mut self, action: &Action, selection: Option<Vector2D<i32>>, brush: B) {
+        if !action.is_change() {
+            return;
+        }
+        let on_selection = matches!(
+            action,
+            Action::Operate(_, Motion::Selection) | Action::Draw { .. }
+        );
+        self.0 = Some(Change {
+            action: action.clone(),
+            selection: selection.filter(|_| on_selection),
+            brush,
+        });
+    }
+
+    /// The change to make again, with `count` in place of the one it was made with
+    pub fn replay(&self, count: Option<u16>) -> Option<Change<B>> {
+        let mut change = self.0.clone()?;
+        if let Some(count) = count {
+            change.action = change.action.with_count(count);
+        }
+        Some(change)
+    }
+}
+
+#[cfg(test)]
+mod repeat_test {
+    use super::*;
+    use crate::core::actions::Operator;
+
+    #[test]
+    fn replaying() {
+        let mut last = LastChange::default();
+        assert_eq!(last.replay(None), None);
+
+        last.record(&Action::Paint(2), Some((1, 1).into()), 'a');
+        last.record(&Action::MoveCursor((1, 0).into()), None, 'b');
+        assert_eq!(
+            last.replay(Some(4)),
+            Some(Change {
+                action: Action::Paint(4),
+                selection: None,
+                brush: 'a'
+            })
+        );
+
+        let delete = Action::Operate(Operator::Delete, Motion::Selection);
+        last.record(&delete, Some((-2, 1).into()), 'c');
+        assert_eq!(last.replay(Some(3)).unwrap().action, delete);
+        assert_eq!(last.replay(None).unwrap().selection, Some((-2, 1).into()));
+    }
+}
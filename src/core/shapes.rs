@@ -0,0 +1,185 @@
+//! Turning shapes into the cells they cover. Every shape is given by two corners,
+//! which are both included.
+
+use super::{CanvasPos, CharID};
+
+/// A piece of a box outline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxPart {
+    Horizontal,
+    Vertical,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// The characters a charset draws box outlines with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxGlyphs {
+    pub horizontal: CharID,
+    pub vertical: CharID,
+    pub top_left: CharID,
+    pub top_right: CharID,
+    pub bottom_left: CharID,
+    pub bottom_right: CharID,
+}
+
+impl BoxGlyphs {
+    /// The single line box characters of code page 437
+    pub const CP437: Self = Self {
+        horizontal: 196,
+        vertical: 179,
+        top_left: 218,
+        top_right: 191,
+        bottom_left: 192,
+        bottom_right: 217,
+    };
+
+    pub fn get(&self, part: BoxPart) -> CharID {
+        match part {
+            BoxPart::Horizontal => self.horizontal,
+            BoxPart::Vertical => self.vertical,
+            BoxPart::TopLeft => self.top_left,
+            BoxPart::TopRight => self.top_right,
+            BoxPart::BottomLeft => self.bottom_left,
+            BoxPart::BottomRight => self.bottom_right,
+        }
+    }
+}
+
+/// The cells along a line, from Bresenham's algorithm
+pub fn line(from: CanvasPos, to: CanvasPos) -> Vec<CanvasPos> {
+    let (mut x, mut y) = (from.x as i32, from.y as i32);
+    let (tx, ty) = (to.x as i32, to.y as i32);
+    let (dx, dy) = ((tx - x).abs(), -(ty - y).abs());
+    let (sx, sy) = ((tx - x).signum(), (ty - y).signum());
+
+    let mut cells = vec![];
+    let mut error = dx + dy;
+    loop {
+        cells.push(CanvasPos::new(x as u16, y as u16));
+        if x == tx && y == ty {
+            return cells;
+        }
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += sx;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += sy;
+        }
+    }
+}
+
+/// The cells of a rectangle, or only its edges when it isn't filled
+pub fn rectangle(min: CanvasPos, max: CanvasPos, filled: bool) -> Vec<CanvasPos> {
+    cells_between(min, max)
+        .filter(|p| filled || p.x == min.x || p.x == max.x || p.y == min.y || p.y == max.y)
+        .collect()
+}
+
+/// The cells whose centres fall inside an ellipse that fits the rectangle, or
+/// only the ones on its rim when it isn't filled
+pub fn ellipse(min: CanvasPos, max: CanvasPos, filled: bool) -> Vec<CanvasPos> {
+    let center = ((min.x + max.x) as f32 / 2., (min.y + max.y) as f32 / 2.);
+    let radii = (
+        (max.x - min.x) as f32 / 2. + 0.5,
+        (max.y - min.y) as f32 / 2. + 0.5,
+    );
+    let inside = |x: i32, y: i32| {
+        let dx = (x as f32 - center.0) / radii.0;
+        let dy = (y as f32 - center.1) / radii.1;
+        dx * dx + dy * dy <= 1.
+    };
+
+    cells_between(min, max)
+        .filter(|p| {
+            let (x, y) = (p.x as i32, p.y as i32);
+            let rim = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                .iter()
+                .any(|(dx, dy)| !inside(x + dx, y + dy));
+            inside(x, y) && (filled || rim)
+        })
+        .collect()
+}
+
+/// Which part of a box's outline a cell is on, if any
+pub fn box_part(p: CanvasPos, min: CanvasPos, max: CanvasPos) -> Option<BoxPart> {
+    let (left, right) = (p.x == min.x, p.x == max.x);
+    let (top, bottom) = (p.y == min.y, p.y == max.y);
+    Some(match (left, right, top, bottom) {
+        // boxes one cell thick are only a line
+        _ if min.y == max.y => BoxPart::Horizontal,
+        _ if min.x == max.x => BoxPart::Vertical,
+        (true, _, true, _) => BoxPart::TopLeft,
+        (_, true, true, _) => BoxPart::TopRight,
+        (true, _, _, true) => BoxPart::BottomLeft,
+        (_, true, _, true) => BoxPart::BottomRight,
+        (_, _, true, _) | (_, _, _, true) => BoxPart::Horizontal,
+        (true, _, _, _) | (_, true, _, _) => BoxPart::Vertical,
+        _ => return None,
+    })
+}
+
+fn cells_between(min: CanvasPos, max: CanvasPos) -> impl Iterator<Item = CanvasPos> {
+    (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| CanvasPos::new(x, y)))
+}
+
+#[cfg(test)]
+mod shapes_test {
+    use super::*;
+    use crate::core::cp437::Cp437;
+
+    fn p(x: u16, y: u16) -> CanvasPos {
+        CanvasPos::new(x, y)
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            line(p(0, 0), p(3, 1)),
+            vec![p(0, 0), p(1, 0), p(2, 1), p(3, 1)]
+        );
+        assert_eq!(line(p(2, 2), p(2, 0)), vec![p(2, 2), p(2, 1), p(2, 0)]);
+        assert_eq!(line(p(1, 1), p(1, 1)), vec![p(1, 1)]);
+    }
+
+    #[test]
+    fn rectangles_and_ellipses() {
+        assert_eq!(rectangle(p(0, 0), p(2, 2), false).len(), 8);
+        assert_eq!(rectangle(p(0, 0), p(2, 2), true).len(), 9);
+
+        let circle = ellipse(p(0, 0), p(4, 4), false);
+        assert!(circle.contains(&p(2, 0)) && circle.contains(&p(0, 2)));
+        assert!(!circle.contains(&p(0, 0)) && !circle.contains(&p(2, 2)));
+        assert!(ellipse(p(0, 0), p(4, 4), true).contains(&p(2, 2)));
+        assert_eq!(ellipse(p(1, 1), p(1, 1), false), vec![p(1, 1)]);
+    }
+
+    #[test]
+    fn box_parts() {
+        let part = |x, y| box_part(p(x, y), p(1, 1), p(3, 2));
+        assert_eq!(part(1, 1), Some(BoxPart::TopLeft));
+        assert_eq!(part(2, 1), Some(BoxPart::Horizontal));
+        assert_eq!(part(3, 2), Some(BoxPart::BottomRight));
+        assert_eq!(box_part(p(2, 2), p(2, 0), p(2, 4)), Some(BoxPart::Vertical));
+        assert_eq!(box_part(p(2, 2), p(0, 0), p(4, 4)), None);
+
+        let glyphs = BoxGlyphs::CP437;
+        let drawn: String = [
+            glyphs.top_left,
+            glyphs.horizontal,
+            glyphs.top_right,
+            glyphs.vertical,
+            glyphs.bottom_left,
+            glyphs.bottom_right,
+        ]
+        .iter()
+        .map(|&id| Cp437::TABLE[id as usize])
+        .collect();
+        assert_eq!(drawn, "┌─┐│└┘");
+    }
+}
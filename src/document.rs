@@ -0,0 +1,493 @@
+//! Sadie's own file format, which keeps everything needed to carry on editing
+//! a canvas later.
+//!
+//! Files are little endian and laid out as:
+//! ```text
+//! magic    "SADIE"
+//! version  u16
+//! size     width: u16, height: u16
+//! font     present: u8, then if present path length: u16, path, then the layout
+//! palette  count: u16, then count × rgba
+//! layers   count: u16, then from the bottom up, count × layer
+//!
+//! layout   name length: u16, name, columns: u16, rows: u16, glyph width: u16,
+//!          glyph height: u16, padding: u16, spacing: u16, codepage: u8
+//! layer    name length: u16, name, visible: u8, locked: u8, opacity: u8,
+//!          then width × height × (id: u16, fg rgba, bg rgba), row by row
+//! ```
+//!
+//! A glyph width or height of 0 is worked out from the image. The codepage is
+//! 0 for none and 1 for code page 437.
+//!
+//! Versions 1 and 2 describe the font with columns: u16, rows: u16, path length:
+//! u16 and path, as a grid of glyphs packed edge to edge. Version 1 files have
+//! a single layer's cells in place of the layers.
+
+use std::{fs, io::ErrorKind, path::Path};
+
+use euclid::default::Size2D;
+use raylib::prelude::Color;
+use thiserror::Error;
+
+use crate::{
+    core::{
+        canvas::{Canvas, CanvasBuilder},
+        layers::Layer,
+        CharID, Charset,
+    },
+    gui::{CellColors, Codepage, FontDescription, FontMetadata},
+    SadieError,
+};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DocumentError {
+    #[error("not a sadie file")]
+    NotADocument,
+
+    #[error(
+        "format version {found} is not supported, this sadie reads version {}",
+        Document::VERSION
+    )]
+    UnsupportedVersion { found: u16 },
+
+    #[error("file ends too early")]
+    Truncated,
+
+    #[error("file goes on after the last cell")]
+    TrailingData,
+
+    #[error("font path is not UTF-8")]
+    InvalidFontPath,
+
+    #[error("font name is not UTF-8")]
+    InvalidFontName,
+
+    #[error("font has codepage {0}, which this sadie doesn't know")]
+    UnknownCodepage(u8),
+
+    #[error("layer name is not UTF-8")]
+    InvalidLayerName,
+
+    #[error("file has no layers")]
+    NoLayers,
+
+    #[error("canvas is {0}×{1}, it needs at least one cell")]
+    EmptyCanvas(u16, u16),
+
+    #[error("canvas is {0}×{1}, but it can hold at most {max} cells", max = u16::MAX)]
+    TooLarge(u16, u16),
+
+    #[error("palette has a single colour, the colour picker needs two")]
+    SingleColor,
+
+    #[error("cell uses character {0}, but the font only has {1}")]
+    UnknownCharacter(CharID, u16),
+}
+
+/// A canvas as it is kept on disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub size: Size2D<u16>,
+    /// The canvas's layers from the bottom up, there's always at least one
+    pub layers: Vec<Layer<CellColors>>,
+    /// The font the cells' characters come from, frontends that don't draw with
+    /// image fonts leave it out
+    pub font: Option<FontDescription>,
+    /// Colours offered by the colour picker
+    pub palette: Vec<Color>,
+}
+
+impl Document {
+    pub const MAGIC: &'static [u8; 5] = b"SADIE";
+    pub const VERSION: u16 = 3;
+
+    /// Copies the layers out of a canvas, without a font or palette
+    pub fn from_canvas<C: Charset>(canvas: &Canvas<C, CellColors>) -> Self {
+        Self {
+            size: canvas.size(),
+            layers: canvas.layers().to_vec(),
+            font: None,
+            palette: vec![],
+        }
+    }
+
+    /// Builds a canvas out of the cells, checking that the charset has every character
+    pub fn to_canvas<T, C>(&self, charset: C) -> Result<Canvas<C, CellColors>, DocumentError>
+    where
+        C: Charset<Item = T>,
+    {
+        let mut cells = self.layers.iter().flat_map(|l| l.cells());
+        if let Some((id, _)) = cells.find(|(id, _)| *id >= charset.len()) {
+            return Err(DocumentError::UnknownCharacter(*id, charset.len()));
+        }
+
+        let builder = CanvasBuilder::init(charset)
+            .cursor_position(0, 0)
+            .size(self.size);
+        Ok(self
+            .layers
+            .iter()
+            .fold(builder, |b, layer| b.layer(layer.clone()))
+            .build())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Self::MAGIC.to_vec();
+        let put_u16 = |out: &mut Vec<u8>, v: u16| out.extend(v.to_le_bytes());
+
+        put_u16(&mut out, Self::VERSION);
+        put_u16(&mut out, self.size.width);
+        put_u16(&mut out, self.size.height);
+
+        match &self.font {
+            Some(font) => {
+                let layout = &font.metadata;
+                out.push(1);
+                put_u16(&mut out, font.path.len() as u16);
+                out.extend(font.path.as_bytes());
+                put_u16(&mut out, layout.name.len() as u16);
+                out.extend(layout.name.as_bytes());
+                for v in [layout.columns, layout.rows] {
+                    put_u16(&mut out, v);
+                }
+                for v in [layout.glyph_width, layout.glyph_height] {
+                    put_u16(&mut out, v.unwrap_or(0));
+                }
+                put_u16(&mut out, layout.padding);
+                put_u16(&mut out, layout.spacing);
+                out.push(match layout.codepage {
+                    None => 0,
+                    Some(Codepage::Cp437) => 1,
+                });
+            }
+            None => out.push(0),
+        }
+
+        put_u16(&mut out, self.palette.len() as u16);
+        for c in &self.palette {
+            out.extend([c.r, c.g, c.b, c.a]);
+        }
+
+        put_u16(&mut out, self.layers.len() as u16);
+        for layer in &self.layers {
+            put_u16(&mut out, layer.name.len() as u16);
+            out.extend(layer.name.as_bytes());
+            out.extend([layer.visible as u8, layer.locked as u8, layer.opacity]);
+            for (id, colors) in layer.cells() {
+                put_u16(&mut out, *id);
+                for c in [colors.fg, colors.bg] {
+                    out.extend([c.r, c.g, c.b, c.a]);
+                }
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DocumentError> {
+        let mut r = Reader(bytes);
+        if r.take(Self::MAGIC.len()) != Ok(Self::MAGIC.as_slice()) {
+            return Err(DocumentError::NotADocument);
+        }
+        let version = match r.u16()? {
+            found @ (1..=Self::VERSION) => found,
+            found => return Err(DocumentError::UnsupportedVersion { found }),
+        };
+
+        let size: Size2D<u16> = (r.u16()?, r.u16()?).into();
+        if size.is_empty() {
+            return Err(DocumentError::EmptyCanvas(size.width, size.height));
+        }
+        if size.width as u32 * size.height as u32 > u16::MAX as u32 {
+            return Err(DocumentError::TooLarge(size.width, size.height));
+        }
+
+        let font = match r.u8()? {
+            0 => None,
+            _ if version < 3 => {
+                let (columns, rows) = (r.u16()?, r.u16()?);
+                let path = r.text(DocumentError::InvalidFontPath)?;
+                Some(FontDescription::grid(path, columns, rows))
+            }
+            _ => {
+                let path = r.text(DocumentError::InvalidFontPath)?;
+                let name = r.text(DocumentError::InvalidFontName)?;
+                let (columns, rows) = (r.u16()?, r.u16()?);
+                let mut length = || r.u16().map(|v| Some(v).filter(|&v| v > 0));
+                let (glyph_width, glyph_height) = (length()?, length()?);
+                let (padding, spacing) = (r.u16()?, r.u16()?);
+                let codepage = match r.u8()? {
+                    0 => None,
+                    1 => Some(Codepage::Cp437),
+                    other => return Err(DocumentError::UnknownCodepage(other)),
+                };
+                Some(FontDescription {
+                    path: path.into(),
+                    metadata: FontMetadata {
+                        name: name.into(),
+                        columns,
+                        rows,
+                        glyph_width,
+                        glyph_height,
+                        padding,
+                        spacing,
+                        codepage,
+                    },
+                })
+            }
+        };
+
+        let colors = r.u16()?;
+        // the picker has a cursor each for the foreground and background
+        if colors == 1 {
+            return Err(DocumentError::SingleColor);
+        }
+        let palette = (0..colors).map(|_| r.color()).collect::<Result<_, _>>()?;
+
+        let layers = if version == 1 {
+            vec![Layer::new("Layer 1", r.cells(size)?, size.width)]
+        } else {
+            let count = r.u16()?;
+            (0..count)
+                .map(|_| {
+                    let name = r.text(DocumentError::InvalidLayerName)?;
+                    let (visible, locked, opacity) = (r.u8()? != 0, r.u8()? != 0, r.u8()?);
+                    let mut layer = Layer::new(name, r.cells(size)?, size.width);
+                    layer.visible = visible;
+                    layer.locked = locked;
+                    layer.opacity = opacity;
+                    Ok(layer)
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        if layers.is_empty() {
+            return Err(DocumentError::NoLayers);
+        }
+
+        if !r.0.is_empty() {
+            return Err(DocumentError::TrailingData);
+        }
+
+        Ok(Self {
+            size,
+            layers,
+            font,
+            palette,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SadieError> {
+        fs::write(path, self.to_bytes()).map_err(SadieError::Io)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SadieError> {
+        let bytes = fs::read(path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => SadieError::CantFindFile {
+                path: path.display().to_string(),
+            },
+            _ => SadieError::Io(e),
+        })?;
+
+        Self::from_bytes(&bytes).map_err(|error| SadieError::BadDocument {
+            path: path.display().to_string(),
+            error,
+        })
+    }
+}
+
+/// Reads values off the front of a slice
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DocumentError> {
+        if self.0.len() < n {
+            return Err(DocumentError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, DocumentError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DocumentError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    /// Text with its length in front, which is `invalid` if it isn't UTF-8
+    fn text(&mut self, invalid: DocumentError) -> Result<&'a str, DocumentError> {
+        let length = self.u16()? as usize;
+        std::str::from_utf8(self.take(length)?).map_err(|_| invalid)
+    }
+
+    fn color(&mut self) -> Result<Color, DocumentError> {
+        let b = self.take(4)?;
+        Ok(Color::new(b[0], b[1], b[2], b[3]))
+    }
+
+    /// A grid of cells, row by row
+    fn cells(&mut self, size: Size2D<u16>) -> Result<Vec<(CharID, CellColors)>, DocumentError> {
+        (0..size.area() as usize)
+            .map(|_| {
+                let id = self.u16()?;
+                let (fg, bg) = (self.color()?, self.color()?);
+                Ok((id, CellColors { fg, bg }))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod document_test {
+    use super::*;
+    use crate::core::{cp437::Cp437, layers::LayerCommand};
+
+    fn document() -> Document {
+        let canvas = CanvasBuilder::init(Cp437)
+            .size((3, 2).into())
+            .default_cells(|id, _| {
+                let fg = Color::new(id as u8 * 40, 0, 0, 255);
+                (b'a' as u16 + id, CellColors { fg, bg: Color::BLUE })
+            })
+            .build();
+        Document {
+            font: Some(FontDescription {
+                path: "gloop_8x16.png".into(),
+                metadata: FontMetadata {
+                    glyph_height: Some(16),
+                    spacing: 1,
+                    ..FontMetadata::grid("Gloop", 16, 8)
+                },
+            }),
+            palette: vec![Color::BLACK, Color::PINK],
+            ..Document::from_canvas(&canvas)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let doc = document();
+        assert_eq!(Document::from_bytes(&doc.to_bytes()), Ok(doc.clone()));
+
+        let canvas = doc.to_canvas(Cp437).unwrap();
+        assert_eq!(canvas.size(), (3, 2).into());
+        assert_eq!(canvas.get(1, 1).0, b'e' as u16);
+
+        let bare = Document::from_canvas(&canvas);
+        assert_eq!(Document::from_bytes(&bare.to_bytes()), Ok(bare));
+    }
+
+    #[test]
+    fn layers() {
+        let mut canvas = document().to_canvas(Cp437).unwrap();
+        canvas
+            .apply_layer(LayerCommand::New(Some("ink".into())))
+            .unwrap();
+        canvas.apply_layer(LayerCommand::Opacity(30)).unwrap();
+        canvas.put(0, 0, (b'!' as u16, CellColors::default()));
+
+        let doc = Document::from_canvas(&canvas);
+        assert_eq!(Document::from_bytes(&doc.to_bytes()), Ok(doc.clone()));
+        let layers = doc.to_canvas(Cp437).unwrap().layers().to_vec();
+        assert_eq!(layers.len(), 2);
+        assert_eq!((layers[1].name.as_str(), layers[1].opacity), ("ink", 30));
+        assert_eq!(layers[1].cells()[0].0, b'!' as u16);
+    }
+
+    #[test]
+    fn reading_version_1() {
+        let mut bytes = Document::MAGIC.to_vec();
+        // version, a 1×1 canvas, no font and no palette
+        bytes.extend([1, 0, 1, 0, 1, 0, 0, 0, 0]);
+        bytes.extend([b'a', 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let doc = Document::from_bytes(&bytes).unwrap();
+        assert_eq!(doc.layers.len(), 1);
+        assert_eq!(
+            doc.layers[0].cells(),
+            &[(
+                b'a' as u16,
+                CellColors {
+                    fg: Color::new(1, 2, 3, 4),
+                    bg: Color::new(5, 6, 7, 8)
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn reading_version_2_fonts() {
+        let mut bytes = Document::MAGIC.to_vec();
+        // version, a 1×1 canvas and a 16×16 font
+        bytes.extend([2, 0, 1, 0, 1, 0, 1, 16, 0, 16, 0, 13, 0]);
+        bytes.extend(b"gloop_8x8.png");
+        // no palette, then a layer with no name
+        bytes.extend([0, 0, 1, 0, 0, 0, 1, 0, 255]);
+        bytes.extend([b'a', 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let font = Document::from_bytes(&bytes).unwrap().font.unwrap();
+        assert_eq!(font, FontDescription::grid("gloop_8x8.png", 16, 16));
+        assert_eq!(font.metadata.name, "gloop_8x8");
+        assert_eq!(font.metadata.codepage, Some(Codepage::Cp437));
+    }
+
+    #[test]
+    fn bad_files() {
+        let bytes = document().to_bytes();
+        assert_eq!(
+            Document::from_bytes(b"PNG\x89"),
+            Err(DocumentError::NotADocument)
+        );
+
+        let mut newer = bytes.clone();
+        newer[5] = 4;
+        assert_eq!(
+            Document::from_bytes(&newer),
+            Err(DocumentError::UnsupportedVersion { found: 4 })
+        );
+
+        assert_eq!(
+            Document::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DocumentError::Truncated)
+        );
+
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(
+            Document::from_bytes(&longer),
+            Err(DocumentError::TrailingData)
+        );
+
+        let mut empty = bytes.clone();
+        empty[7] = 0;
+        assert_eq!(
+            Document::from_bytes(&empty),
+            Err(DocumentError::EmptyCanvas(0, 2))
+        );
+
+        let mut huge = bytes.clone();
+        huge[7..11].copy_from_slice(&[0, 1, 0, 1]);
+        assert_eq!(
+            Document::from_bytes(&huge),
+            Err(DocumentError::TooLarge(256, 256))
+        );
+
+        let mut doc = document();
+        doc.palette.truncate(1);
+        assert_eq!(
+            Document::from_bytes(&doc.to_bytes()),
+            Err(DocumentError::SingleColor)
+        );
+
+        let mut doc = document();
+        let mut cells = doc.layers[0].cells().to_vec();
+        cells[4].0 = 300;
+        doc.layers[0] = Layer::new("bad", cells, 3);
+        assert_eq!(
+            doc.to_canvas(Cp437).err(),
+            Some(DocumentError::UnknownCharacter(300, 256))
+        );
+    }
+}
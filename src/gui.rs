@@ -1,22 +1,46 @@
 // Contains stuff for sourcing images for fonts and rendering them
 mod font;
+// Drawing canvases into images that can be saved
+mod export;
 mod palette;
 // Contains the components of the GUI that the users use to paint to a canvas
 // mod user_interface;
 
 // More agnostic way of describing how to draw canvases
 mod gallery;
+// Working out where frames go on the screen
+mod layout;
 
-use euclid::default::Size2D;
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+
+use euclid::default::{Point2D, Size2D};
 use font::TextmodeFont;
 use palette::Palette;
 
 /// GUI for image based textmode. Powered by raylib
 use raylib::prelude::*;
 
-use crate::core::canvas::CanvasBuilder;
-use crate::core::Charset;
-use crate::gui::gallery::{CellColors, GuiGallery};
+use crate::core::actions::{Action, ActionError, Mode};
+use crate::core::canvas::{Anchor, Canvas, CanvasBuilder, Edge};
+use crate::core::command::Command;
+use crate::core::command_line::CommandLine;
+use crate::core::gallery::Gallery;
+use crate::core::input::{
+    InputEvent, Key, KeyEvent, Modifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use crate::core::layers::LayerCommand;
+use crate::core::registers::Registers;
+use crate::core::repeat::LastChange;
+use crate::core::{shapes, CanvasPos, CharID, Charset};
+use crate::document::Document;
+pub use crate::gui::export::export_document;
+pub use crate::gui::font::{Codepage, FontDescription, FontError, FontMetadata};
+pub use crate::gui::gallery::CellColors;
+use crate::gui::gallery::{DrawableCanvas, GuiGallery, CID};
+use crate::gui::layout::Dock;
 use crate::{SadieContext, SadieError};
 
 pub trait GuiCharset: Charset {
@@ -44,15 +68,67 @@ pub struct RaylibContext {
     rl: RaylibHandle,
     rt: RaylibThread,
     gallery: GuiGallery,
+    /// The canvas being painted, which is what gets saved and opened
+    art: Option<CID>,
+    charset_picker: Option<CID>,
+    color_picker: Option<CID>,
+    /// Where `:w` saves to when it isn't given a file
+    file: Option<PathBuf>,
+    /// Input that hasn't been handed out yet
+    events: VecDeque<InputEvent>,
+    /// Yanked cells, kept when another file is opened
+    registers: Registers<CellColors>,
+    /// What `.` makes again, with the brush it was made with
+    last_change: LastChange<Option<(CharID, CellColors)>>,
+    /// Shown in the bar along the bottom of the window
+    status: String,
+    /// The command line being typed and where its cursor is, shown instead of
+    /// the status
+    prompt: Option<(String, usize)>,
+    /// The register a macro is being recorded into
+    recording: Option<char>,
+    /// The cell a stroke of paint last reached, so dragging quickly leaves no gaps
+    stroke: Option<CanvasPos>,
+}
+
+/// Height in pixels of the bar along the bottom of the window
+const STATUS_HEIGHT: i32 = 24;
+const STATUS_FONT_SIZE: i32 = 20;
+
+/// Lays the characters out the same way as the font's image
+fn make_charset_picker(charset: TextmodeFont) -> Canvas<TextmodeFont> {
+    let font = charset.description();
+    let size = (font.metadata.columns, font.metadata.rows).into();
+    CanvasBuilder::init(charset)
+        .size(size)
+        .cursor_position(0, 0)
+        .cursor_edge(Edge::Wrap)
+        .char_cascade()
+        .build()
 }
 
-impl Default for RaylibContext {
-    fn default() -> Self {
-        let (mut rl, rt) = raylib::init().size(800, 800).title("Sadie").build();
+fn make_color_picker(palette: Palette) -> Canvas<Palette> {
+    CanvasBuilder::init(palette)
+        .cursor_position(0, 0)
+        .cursor_position(1, 0)
+        .cursor_edge(Edge::Wrap)
+        .char_cascade()
+        .build()
+}
+
+impl RaylibContext {
+    /// Opens a window with a blank canvas, drawn with the font in an image
+    pub fn new(font: &str) -> Result<Self, SadieError> {
+        let (mut rl, rt) = raylib::init()
+            .size(800, 800)
+            .resizable()
+            .title("Sadie")
+            .build();
 
         rl.set_exit_key(None);
 
-        let charset = TextmodeFont::load_charset(&mut rl, &rt, "gloop_8x8.png", 16, 16).unwrap();
+        let charset = TextmodeFont::load(&mut rl, &rt, &FontDescription::for_image(font)?)?;
+        rl.set_window_title(&rt, &window_title(&charset));
         let user_canvas = CanvasBuilder::init(charset.clone())
             .cursor_position(0, 0)
             .size((12, 8).into())
@@ -64,44 +140,326 @@ impl Default for RaylibContext {
             })
             .build();
         let mut gallery = GuiGallery::new();
-        if gallery.add_colored_font(&mut rl, &rt, user_canvas).is_err() {
-            println!("Couldn't add user canvas");
-        }
+        let art = match gallery.add_colored_font(&mut rl, &rt, user_canvas, Dock::Float) {
+            Ok(id) => Some(id),
+            Err(_) => {
+                println!("Couldn't add user canvas");
+                None
+            }
+        };
 
-        let charset_picker = CanvasBuilder::init(charset.clone())
-            .cursor_position(0, 0)
-            .char_cascade()
-            .build();
+        let charset_picker = make_charset_picker(charset.clone());
+        let charset_picker = match gallery.add_font_only(&mut rl, &rt, charset_picker, Dock::Left) {
+            Ok(id) => Some(id),
+            Err(_) => {
+                println!("Couldn't add charset picker");
+                None
+            }
+        };
 
-        if gallery
-            .add_font_only(&mut rl, &rt, charset_picker)
-            .map(|e| e.and_modify(|f| f.position = (0, 200).into()))
-            .is_err()
+        let color_picker = make_color_picker(Palette::default());
+        let color_picker = match gallery.add_color_squares(&mut rl, &rt, color_picker, Dock::Bottom)
         {
-            println!("Couldn't add charset picker");
+            Ok(id) => Some(id),
+            Err(_) => {
+                println!("Couldn't add color picker");
+                None
+            }
+        };
+
+        gallery.layout(screen_size(&rl));
+
+        Ok(Self {
+            rl,
+            rt,
+            gallery,
+            art,
+            charset_picker,
+            color_picker,
+            file: None,
+            events: VecDeque::new(),
+            registers: Registers::default(),
+            last_change: LastChange::default(),
+            status: String::new(),
+            prompt: None,
+            recording: None,
+            stroke: None,
+        })
+    }
+}
+
+fn window_title(font: &TextmodeFont) -> String {
+    format!("Sadie - {}", font.name())
+}
+
+/// The space frames are laid out in, which is the window above the status bar
+fn screen_size(rl: &RaylibHandle) -> Size2D<i32> {
+    Size2D::new(
+        rl.get_screen_width(),
+        rl.get_screen_height() - STATUS_HEIGHT,
+    )
+}
+
+fn draw_status_bar(d: &mut impl RaylibDraw, width: i32, top: i32, text: &str, cursor: Option<i32>) {
+    d.draw_rectangle(0, top, width, STATUS_HEIGHT, Color::DARKGRAY);
+    let (x, y) = (4, top + (STATUS_HEIGHT - STATUS_FONT_SIZE) / 2);
+    d.draw_text(text, x, y, STATUS_FONT_SIZE, Color::WHITE);
+    if let Some(cursor) = cursor {
+        d.draw_rectangle(x + cursor, y, 2, STATUS_FONT_SIZE, Color::WHITE);
+    }
+}
+
+impl RaylibContext {
+    fn art(&self) -> Option<&Canvas<TextmodeFont, CellColors>> {
+        match self.art.and_then(|id| self.gallery.get_canvas(id)) {
+            Some(DrawableCanvas::ColoredFont(c)) => Some(c),
+            _ => None,
         }
+    }
 
-        let color_picker = CanvasBuilder::init(Palette::default())
-            .cursor_position(0, 0)
-            .cursor_position(1, 0)
-            .char_cascade()
-            .build();
+    /// Saves the art along with its font and the colour picker's palette
+    fn write(&mut self, path: Option<PathBuf>) -> Result<(), SadieError> {
+        let path = path
+            .or_else(|| self.file.clone())
+            .ok_or(SadieError::NoFileName)?;
+        let Some(art) = self.art() else {
+            return Ok(());
+        };
 
-        if gallery
-            .add_color_squares(&mut rl, &rt, color_picker)
-            .map(|e| e.and_modify(|f| f.position = (0, 300).into()))
-            .is_err()
-        {
-            println!("Couldn't add color picker");
+        let palette = match self.color_picker.and_then(|id| self.gallery.get_canvas(id)) {
+            Some(DrawableCanvas::ColorSquares(c)) => c.charset().colors().to_vec(),
+            _ => vec![],
+        };
+        let document = Document {
+            font: Some(art.charset().description().clone()),
+            palette,
+            ..Document::from_canvas(art)
+        };
+
+        document.save(&path)?;
+        self.status = format!("\"{}\" written", path.display());
+        self.file = Some(path);
+        Ok(())
+    }
+
+    /// The glyph under the charset picker's cursor, coloured with what's under
+    /// the colour picker's first cursor for the foreground and second for the
+    /// background
+    fn brush(&self) -> Option<(CharID, CellColors)> {
+        let picker = |id: Option<CID>| id.and_then(|id| self.gallery.get_canvas(id));
+        let (Some(DrawableCanvas::FontOnly(charset)), Some(DrawableCanvas::ColorSquares(palette))) =
+            (picker(self.charset_picker), picker(self.color_picker))
+        else {
+            return None;
+        };
+
+        let (glyph, _) = charset.under_cursors().next()?;
+        let mut colors = palette
+            .under_cursors()
+            .map(|(id, _)| palette.charset().get_char(*id));
+        let (fg, bg) = (colors.next()?, colors.next()?);
+        Some((*glyph, CellColors { fg, bg }))
+    }
+
+    /// Changes the art's layers, then says which one edits go to now
+    fn layer(&mut self, command: LayerCommand) -> Result<(), SadieError> {
+        if let Some(frame) = self.art.and_then(|id| self.gallery.get_mut(id)) {
+            frame.apply_layer(command).map_err(SadieError::Layer)?;
+        }
+        if let Some(art) = self.art() {
+            let (layers, active) = (art.layers(), art.active_layer());
+            self.status = format!(
+                "layer {} of {}: {}",
+                active + 1,
+                layers.len(),
+                layers[active].name
+            );
+        }
+        Ok(())
+    }
+
+    /// What the status bar says, and how far along it the command line's cursor is
+    fn status_line(&self) -> (String, Option<i32>) {
+        match &self.prompt {
+            Some((line, at)) => {
+                let text = format!(":{line}");
+                let before: String = text.chars().take(1 + at).collect();
+                let cursor = self.rl.measure_text(&before, STATUS_FONT_SIZE);
+                (text, Some(cursor))
+            }
+            None if self.status.is_empty() => (self.mode().status(self.recording), None),
+            None => (self.status.clone(), None),
         }
+    }
 
-        Self { rl, rt, gallery }
+    fn export(&mut self, path: &Path, scale: u16) -> Result<(), SadieError> {
+        let Some(art) = self.art() else {
+            return Ok(());
+        };
+        let glyphs = art
+            .charset()
+            .source
+            .load_image()
+            .map_err(SadieError::Raylib)?;
+        export::export_png(art, &glyphs, scale, path)?;
+        self.status = format!("\"{}\" exported", path.display());
+        Ok(())
+    }
+
+    /// Replaces the art with a saved one, along with the pickers when the file
+    /// brings its own font or palette
+    fn edit(&mut self, path: PathBuf) -> Result<(), SadieError> {
+        let document = Document::load(&path)?;
+        let (Some(art_id), Some(current)) = (self.art, self.art().map(|a| a.charset().clone()))
+        else {
+            return Ok(());
+        };
+
+        let charset = match &document.font {
+            Some(font) => TextmodeFont::load(&mut self.rl, &self.rt, font)?,
+            None => current,
+        };
+        let art = document
+            .to_canvas(charset.clone())
+            .map_err(|error| SadieError::BadDocument {
+                path: path.display().to_string(),
+                error,
+            })?;
+
+        let (rl, rt) = (&mut self.rl, &self.rt);
+        self.gallery
+            .replace(rl, rt, art_id, DrawableCanvas::ColoredFont(art))?;
+        if let (Some(id), Some(_)) = (self.charset_picker, &document.font) {
+            rl.set_window_title(rt, &window_title(&charset));
+            let picker = make_charset_picker(charset);
+            self.gallery
+                .replace(rl, rt, id, DrawableCanvas::FontOnly(picker))?;
+        }
+        if let (Some(id), false) = (self.color_picker, document.palette.is_empty()) {
+            let picker = make_color_picker(document.palette.into());
+            self.gallery
+                .replace(rl, rt, id, DrawableCanvas::ColorSquares(picker))?;
+        }
+
+        // the new canvases can be a different size to the old ones
+        self.gallery.layout(screen_size(&self.rl));
+        self.file = Some(path);
+        Ok(())
+    }
+
+    /// Resizes the art, new cells are blank like deleted ones
+    fn resize(&mut self, size: Size2D<u16>, anchor: Anchor) -> Result<(), SadieError> {
+        let Some(art) = self.art else {
+            return Ok(());
+        };
+        let fill = (0, CellColors::default());
+        self.gallery
+            .resize(&mut self.rl, &self.rt, art, size, anchor, fill)?;
+        self.gallery.layout(screen_size(&self.rl));
+        Ok(())
+    }
+}
+
+impl RaylibContext {
+    /// Queues up everything that happened since the last frame, as raylib only
+    /// knows what was pressed during the current one
+    fn poll_input(&mut self) {
+        let held = |left, right| self.rl.is_key_down(left) || self.rl.is_key_down(right);
+        let modifiers = Modifiers {
+            ctrl: held(
+                KeyboardKey::KEY_LEFT_CONTROL,
+                KeyboardKey::KEY_RIGHT_CONTROL,
+            ),
+            alt: held(KeyboardKey::KEY_LEFT_ALT, KeyboardKey::KEY_RIGHT_ALT),
+            shift: held(KeyboardKey::KEY_LEFT_SHIFT, KeyboardKey::KEY_RIGHT_SHIFT),
+        };
+
+        // keys that don't type anything, or that are typed with ctrl or alt held
+        // and so never show up as characters
+        let special = [
+            (KeyboardKey::KEY_ESCAPE, Key::Escape),
+            (KeyboardKey::KEY_ENTER, Key::Enter),
+            (KeyboardKey::KEY_TAB, Key::Tab),
+            (KeyboardKey::KEY_BACKSPACE, Key::Backspace),
+            (KeyboardKey::KEY_UP, Key::Up),
+            (KeyboardKey::KEY_DOWN, Key::Down),
+            (KeyboardKey::KEY_LEFT, Key::Left),
+            (KeyboardKey::KEY_RIGHT, Key::Right),
+        ];
+        // raylib queues keys apart from the characters they type. Every printable
+        // key, from space to backtick and on the keypad, is taken to have typed
+        // one character, in the order the keys were pressed, so each takes the
+        // next character and `x` then `<Esc>` stay in that order. Characters
+        // with no key of their own, like ones from an input method, come last.
+        let types_char = |key: KeyboardKey| matches!(key as u32, 32..=96 | 320..=336);
+        while let Some(pressed) = self.rl.get_key_pressed() {
+            let key = match special.iter().find(|(k, _)| *k == pressed) {
+                Some(&(_, key)) => KeyEvent::new(key, modifiers),
+                None if modifiers.ctrl || modifiers.alt => {
+                    match char::from_u32(pressed as u32).filter(char::is_ascii_alphanumeric) {
+                        Some(c) => KeyEvent::new(Key::Char(c.to_ascii_lowercase()), modifiers),
+                        None => continue,
+                    }
+                }
+                None if types_char(pressed) => match self.rl.get_char_pressed() {
+                    Some(c) => c.into(),
+                    None => continue,
+                },
+                None => continue,
+            };
+            self.events.push_back(InputEvent::Key(key));
+        }
+        while let Some(c) = self.rl.get_char_pressed() {
+            if !(modifiers.ctrl || modifiers.alt) {
+                self.events.push_back(InputEvent::Key(c.into()));
+            }
+        }
+
+        let mouse = self.rl.get_mouse_position();
+        let position = Point2D::new(mouse.x as i32, mouse.y as i32);
+        let delta = self.rl.get_mouse_delta();
+        let moved = delta.x != 0. || delta.y != 0.;
+        let buttons = [
+            (
+                raylib::consts::MouseButton::MOUSE_BUTTON_LEFT,
+                MouseButton::Left,
+            ),
+            (
+                raylib::consts::MouseButton::MOUSE_BUTTON_RIGHT,
+                MouseButton::Right,
+            ),
+            (
+                raylib::consts::MouseButton::MOUSE_BUTTON_MIDDLE,
+                MouseButton::Middle,
+            ),
+        ];
+        for (rl_button, button) in buttons {
+            let kind = if self.rl.is_mouse_button_pressed(rl_button) {
+                MouseEventKind::Press(button)
+            } else if self.rl.is_mouse_button_released(rl_button) {
+                MouseEventKind::Release(button)
+            } else if moved && self.rl.is_mouse_button_down(rl_button) {
+                MouseEventKind::Drag(button)
+            } else {
+                continue;
+            };
+            self.events
+                .push_back(InputEvent::Mouse(MouseEvent { kind, position }));
+        }
+
+        let wheel = self.rl.get_mouse_wheel_move();
+        if wheel != 0. {
+            let kind = MouseEventKind::Scroll(wheel.signum() as i32);
+            self.events
+                .push_back(InputEvent::Mouse(MouseEvent { kind, position }));
+        }
     }
 }
 
 impl SadieContext for RaylibContext {
-    fn check_input(&mut self) -> Option<char> {
-        self.rl.get_char_pressed()
+    fn check_input(&mut self) -> Option<InputEvent> {
+        self.poll_input();
+        self.events.pop_front()
     }
 
     fn is_alive(&self) -> bool {
@@ -109,13 +467,134 @@ impl SadieContext for RaylibContext {
     }
 
     fn draw(&mut self) {
+        if self.rl.is_window_resized() {
+            self.gallery.layout(screen_size(&self.rl));
+        }
+
+        let (text, cursor) = self.status_line();
+        let (width, top) = (self.rl.get_screen_width(), screen_size(&self.rl).height);
         let mut d = self.rl.begin_drawing(&self.rt);
         d.clear_background(Color::WHITE);
 
         self.gallery.draw(&mut d, &self.rt);
+        draw_status_bar(&mut d, width, top, &text, cursor);
+    }
+
+    /// Clicking picks from the pickers and moves the art's cursor, dragging over
+    /// the art paints. The right button works the colour picker's second cursor.
+    fn handle_mouse(&mut self, event: MouseEvent) -> Result<(), SadieError> {
+        if let MouseEventKind::Release(_) = event.kind {
+            // a stroke of paint is undone all at once
+            self.stroke = None;
+            if let Some(frame) = self.art.and_then(|id| self.gallery.get_mut(id)) {
+                frame.contents_mut().commit();
+            }
+            return Ok(());
+        }
+
+        let Some((id, cell)) = self.gallery.hit(event.position) else {
+            return Ok(());
+        };
+        let brush = self.brush().filter(|_| Some(id) == self.art);
+        if let MouseEventKind::Press(_) = event.kind {
+            self.gallery.focus(id);
+        }
+        let Some(frame) = self.gallery.get_mut(id) else {
+            return Ok(());
+        };
+
+        match event.kind {
+            MouseEventKind::Press(button) | MouseEventKind::Drag(button) => {
+                let cursor = if button == MouseButton::Right { 1 } else { 0 };
+                let contents = frame.contents_mut();
+                contents.place_cursor(cursor, cell);
+                if let (MouseButton::Left, Some(brush)) = (button, brush) {
+                    let from = match event.kind {
+                        MouseEventKind::Drag(_) => self.stroke.unwrap_or(cell),
+                        _ => cell,
+                    };
+                    for p in shapes::line(from, cell) {
+                        contents.stamp(p, brush);
+                    }
+                    self.stroke = Some(cell);
+                } else {
+                    // dragging over a picker breaks the line instead of joining up across it
+                    self.stroke = None;
+                }
+            }
+            MouseEventKind::Scroll(lines) => frame.apply(Action::MoveCursor((0, -lines).into())),
+            MouseEventKind::Release(_) => {}
+        }
+        Ok(())
+    }
+
+    fn apply_actions(&mut self, action: Action) -> Result<(), SadieError> {
+        self.status.clear();
+        if let Action::Command(command) = action {
+            let result = match command {
+                Command::Write(path) => self.write(path),
+                Command::Edit(path) => self.edit(path),
+                Command::Export { path, scale } => self.export(&path, scale),
+                Command::Resize { size, anchor } => self.resize(size, anchor),
+                Command::Layer(command) => self.layer(command),
+            };
+            // a file that can't be saved or opened shouldn't close the window
+            if let Err(e) = result {
+                self.status = format!("E: {e}");
+            }
+            return Ok(());
+        }
+
+        if let Action::CycleFocus(steps) = action {
+            self.gallery.cycle_focus(steps);
+            return Ok(());
+        }
+
+        let (action, brush) = match action {
+            Action::Repeat(count) => {
+                let (Some(change), Some(frame)) =
+                    (self.last_change.replay(count), self.gallery.focused_mut())
+                else {
+                    return Ok(());
+                };
+                if let Some(offset) = change.selection {
+                    frame.reselect(offset);
+                }
+                (change.action, change.brush)
+            }
+            action => (action, self.brush()),
+        };
+        if let Some(frame) = self.gallery.focused().filter(|f| f.is_art()) {
+            self.last_change
+                .record(&action, frame.selection_offset(), brush);
+        }
+
+        if action.needs_brush() {
+            if let (Some(brush), Some(frame)) = (brush, self.gallery.focused_mut()) {
+                frame.apply_brush(action, brush);
+            }
+            return Ok(());
+        }
+
+        if let Some(frame) = self.gallery.focused_mut() {
+            frame.apply_with(action, &mut self.registers);
+        }
+        Ok(())
+    }
+
+    fn mode(&self) -> Mode {
+        self.gallery.focused().map_or(Mode::Normal, |f| f.mode())
+    }
+
+    fn report_error(&mut self, error: ActionError) {
+        self.status = format!("E: {error}");
+    }
+
+    fn show_command_line(&mut self, line: Option<&CommandLine>) {
+        self.prompt = line.map(|l| (l.text().to_string(), l.cursor()));
     }
 
-    fn apply_actions(&mut self, action: crate::core::actions::Action) -> Result<(), SadieError> {
-        todo!()
+    fn show_recording(&mut self, register: Option<char>) {
+        self.recording = register;
     }
 }
@@ -0,0 +1,87 @@
+use std::{fs, path::Path};
+
+use raylib::prelude::*;
+
+use crate::{
+    core::canvas::Canvas,
+    document::Document,
+    gui::{
+        font::{clear_black, ImageFont},
+        CellColors, GuiCharset,
+    },
+    SadieError,
+};
+
+/// Composites the cells of a canvas on the CPU, one image pixel for each font
+/// pixel. White glyph pixels take the cell's foreground colour and the rest is
+/// filled with its background.
+pub fn render_image<C>(canvas: &Canvas<C, CellColors>, glyphs: &Image) -> Image
+where
+    C: GuiCharset<Item = Rectangle>,
+{
+    let char_size = canvas.charset().get_char_size();
+    let (w, h) = (char_size.width as i32, char_size.height as i32);
+    let size = canvas.size();
+
+    // black is where the background shows through
+    let mut glyphs = glyphs.clone();
+    clear_black(&mut glyphs);
+
+    let mut image = Image::gen_image_color(
+        size.width as i32 * w,
+        size.height as i32 * h,
+        Color::BLANK,
+    );
+    for (x, y, quad, colors) in canvas.cells() {
+        let (px, py) = (x as i32 * w, y as i32 * h);
+        image.draw_rectangle(px, py, w, h, colors.bg);
+        image.draw(
+            &glyphs,
+            quad,
+            Rectangle::new(px as f32, py as f32, w as f32, h as f32),
+            colors.fg,
+        );
+    }
+    image
+}
+
+/// Writes a canvas out as a PNG, with every pixel blown up into a `scale` sized square
+pub fn export_png<C>(
+    canvas: &Canvas<C, CellColors>,
+    glyphs: &Image,
+    scale: u16,
+    path: &Path,
+) -> Result<(), SadieError>
+where
+    C: GuiCharset<Item = Rectangle>,
+{
+    let mut image = render_image(canvas, glyphs);
+    if scale > 1 {
+        // nearest neighbour keeps the pixels crisp
+        image.resize_nn(image.width() * scale as i32, image.height() * scale as i32);
+    }
+
+    let png = image
+        .export_image_to_memory(".png")
+        .map_err(SadieError::Raylib)?;
+    fs::write(path, png).map_err(SadieError::Io)
+}
+
+/// Exports a saved document with the font it names, which works without a window.
+/// `source` is the file the document came from, if it has been saved.
+pub fn export_document(
+    document: &Document,
+    source: Option<&Path>,
+    scale: u16,
+    path: &Path,
+) -> Result<(), SadieError> {
+    let description = document.font.as_ref().ok_or(SadieError::NoFont)?;
+    let canvas = document
+        .to_canvas(ImageFont::load(description)?)
+        .map_err(|error| SadieError::BadDocument {
+            path: source.map_or("[No Name]".into(), |s| s.display().to_string()),
+            error,
+        })?;
+
+    export_png(&canvas, &canvas.charset().image, scale, path)
+}
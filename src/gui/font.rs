@@ -1,9 +1,10 @@
 use euclid::default::Size2D;
-use raylib::prelude::*;
-use std::{collections::HashMap, ops::Deref, rc::Rc};
+use raylib::{consts::PixelFormat, prelude::*};
+use std::{collections::HashMap, fs, io::ErrorKind, ops::Deref, path::Path, rc::Rc};
+use thiserror::Error;
 
 use crate::{
-    core::{CharID, Charset},
+    core::{shapes::BoxGlyphs, CharID, Charset},
     gui::GuiCharset,
     SadieError,
 };
@@ -12,27 +13,107 @@ use crate::{
 mod textmode_font_test {
     use super::*;
 
+    fn quad(x: f32, y: f32, width: f32, height: f32) -> Rectangle {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
     #[test]
     fn making_char_quads() {
-        let qs = TextmodeFont::make_char_quads(128, 128, 16, 16);
+        let grid = FontMetadata::grid("gloop", 16, 16);
+        let qs = TextmodeFont::make_char_quads((128, 128).into(), &grid).unwrap();
+        assert_eq!(qs.get(&0), Some(&quad(0., 0., 8., 8.)));
+        assert_eq!(qs.get(&255), Some(&quad(120., 120., 8., 8.)));
+
+        // 1 + 4 × 8 + 3 × 2 + 1 across and 1 + 2 × 8 + 1 × 2 + 1 down
+        let spaced = FontMetadata {
+            padding: 1,
+            spacing: 2,
+            ..FontMetadata::grid("spaced", 4, 2)
+        };
+        let qs = TextmodeFont::make_char_quads((40, 20).into(), &spaced).unwrap();
+        assert_eq!(qs.len(), 8);
+        assert_eq!(qs.get(&5), Some(&quad(11., 11., 8., 8.)));
+    }
+
+    #[test]
+    fn uneven_grids() {
+        let quads = |size: (i32, i32), font| TextmodeFont::make_char_quads(size.into(), &font);
         assert_eq!(
-            qs.get(&0),
-            Some(&Rectangle {
-                x: 0.,
-                y: 0.,
-                width: 8.,
-                height: 8.
+            quads((130, 128), FontMetadata::grid("", 16, 16)),
+            Err(FontError::Uneven {
+                dimension: "wide",
+                pixels: 130,
+                glyphs: 16
             })
         );
+        let sized = FontMetadata {
+            glyph_height: Some(7),
+            ..FontMetadata::grid("", 16, 16)
+        };
+        assert_eq!(
+            quads((128, 128), sized).unwrap_err().to_string(),
+            "the image is 128 pixels tall, but the font's layout needs 112"
+        );
+        assert_eq!(
+            quads((128, 128), FontMetadata::grid("", 0, 16)),
+            Err(FontError::NoGlyphs)
+        );
+        assert_eq!(
+            quads((512, 512), FontMetadata::grid("", 256, 256)),
+            Err(FontError::TooManyGlyphs(65536))
+        );
+    }
 
+    #[test]
+    fn reading_metadata() {
+        let font = FontMetadata::parse(
+            r#"
+            name = "Gloop"
+            columns = 16
+            rows = 8
+            glyph_height = 16
+            spacing = 1
+            codepage = "cp437"
+            "#,
+            "gloop_8x16",
+        )
+        .unwrap();
         assert_eq!(
-            qs.get(&255),
-            Some(&Rectangle {
-                x: 120.0,
-                y: 120.0,
-                width: 8.,
-                height: 8.
-            })
+            font,
+            FontMetadata {
+                name: "Gloop".into(),
+                columns: 16,
+                rows: 8,
+                glyph_width: None,
+                glyph_height: Some(16),
+                padding: 0,
+                spacing: 1,
+                codepage: Some(Codepage::Cp437),
+            }
+        );
+
+        let parse = |text| FontMetadata::parse(text, "font");
+        assert_eq!(parse("columns = 4\nrows = 4").unwrap().name, "font");
+        assert_eq!(
+            parse("columns = 16").unwrap_err(),
+            FontError::Missing("rows")
+        );
+        assert_eq!(
+            parse("columns = -1\nrows = 1"),
+            Err(FontError::NotANumber("columns".into()))
+        );
+        assert_eq!(
+            parse("columns = 1\nrows = 1\nspaceing = 1"),
+            Err(FontError::UnknownField("spaceing".into()))
+        );
+        assert_eq!(
+            parse("columns = 1\nrows = 1\ncodepage = \"cp850\""),
+            Err(FontError::UnknownCodepage("cp850".into()))
         );
     }
 }
@@ -59,10 +140,262 @@ impl Deref for TextmodeFontSource {
     }
 }
 
+/// Where a font's image is and how its characters are laid out, enough to load
+/// it again. Documents keep the whole layout, so they open the same way even if
+/// the metadata beside the image changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontDescription {
+    pub path: String,
+    pub metadata: FontMetadata,
+}
+
+impl FontDescription {
+    /// Describes the font in an image, going by its metadata. Images without
+    /// any are taken to be a 16 by 16 grid, as long as they split into one.
+    pub fn for_image(path: &str) -> Result<Self, SadieError> {
+        let metadata = match FontMetadata::load(path)? {
+            Some(metadata) => metadata,
+            None => {
+                let grid = FontMetadata::grid(&image_name(path), 16, 16);
+                let image = Image::load_image(path).map_err(SadieError::Raylib)?;
+                let size = (image.width(), image.height()).into();
+                if TextmodeFont::make_char_quads(size, &grid).is_err() {
+                    return Err(SadieError::BadFont {
+                        path: path.into(),
+                        error: FontError::NoMetadata(metadata_path(path)),
+                    });
+                }
+                grid
+            }
+        };
+        Ok(Self {
+            path: path.into(),
+            metadata,
+        })
+    }
+
+    /// A font whose glyphs are packed edge to edge in a grid, which is all
+    /// documents from before fonts had metadata say about them
+    pub fn grid(path: &str, columns: u16, rows: u16) -> Self {
+        Self {
+            path: path.into(),
+            metadata: FontMetadata::grid(&image_name(path), columns, rows),
+        }
+    }
+}
+
+/// What a font is called when its metadata doesn't say
+fn image_name(image: &str) -> String {
+    Path::new(image)
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().into())
+}
+
+/// Where the metadata for the font in an image is kept
+fn metadata_path(image: &str) -> String {
+    Path::new(image)
+        .with_extension("toml")
+        .display()
+        .to_string()
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FontError {
+    #[error("{0}")]
+    Toml(String),
+
+    #[error("`{0}` is missing")]
+    Missing(&'static str),
+
+    #[error("`{0}` should be a whole number from 0 to 65535")]
+    NotANumber(String),
+
+    #[error("`{0}` should be text, in quotes")]
+    NotText(String),
+
+    #[error("`{0}` isn't something fonts have")]
+    UnknownField(String),
+
+    #[error("there's no `{0}` codepage, the only one is `cp437`")]
+    UnknownCodepage(String),
+
+    #[error(
+        "there's no `{0}` to say how the glyphs are laid out, and they aren't a 16 by 16 grid"
+    )]
+    NoMetadata(String),
+
+    #[error("the grid has no glyphs")]
+    NoGlyphs,
+
+    #[error("a font can have at most 65535 glyphs, this one has {0}")]
+    TooManyGlyphs(u32),
+
+    #[error(
+        "the image is {pixels} pixels {dimension}, which doesn't split evenly into {glyphs} glyphs"
+    )]
+    Uneven {
+        dimension: &'static str,
+        pixels: i32,
+        glyphs: u16,
+    },
+
+    #[error("the image is {pixels} pixels {dimension}, but the font's layout needs {needed}")]
+    WrongSize {
+        dimension: &'static str,
+        pixels: i32,
+        needed: i32,
+    },
+}
+
+/// Which glyph of a font is which
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codepage {
+    Cp437,
+}
+
+/// How a font's image is split into glyphs. It's read from a TOML file with the
+/// same name as the image, like `gloop_8x8.toml` beside `gloop_8x8.png`:
+///
+/// ```toml
+/// name = "Gloop"
+/// columns = 16
+/// rows = 16
+/// # worked out from the size of the image when left out
+/// glyph_width = 8
+/// glyph_height = 8
+/// # pixels around the edge of the grid, and between neighbouring glyphs
+/// padding = 0
+/// spacing = 0
+/// codepage = "cp437"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontMetadata {
+    /// What the font is called where it's shown, the image's name by default
+    pub name: String,
+    pub columns: u16,
+    pub rows: u16,
+    pub glyph_width: Option<u16>,
+    pub glyph_height: Option<u16>,
+    pub padding: u16,
+    pub spacing: u16,
+    /// Fonts laid out like a codepage can draw boxes
+    pub codepage: Option<Codepage>,
+}
+
+impl FontMetadata {
+    /// Glyphs packed edge to edge, which is how images without metadata are read.
+    /// Those with a full set of characters are taken to be code page 437.
+    pub fn grid(name: &str, columns: u16, rows: u16) -> Self {
+        Self {
+            name: name.into(),
+            columns,
+            rows,
+            glyph_width: None,
+            glyph_height: None,
+            padding: 0,
+            spacing: 0,
+            codepage: (columns as u32 * rows as u32 >= 256).then_some(Codepage::Cp437),
+        }
+    }
+
+    pub fn parse(text: &str, default_name: &str) -> Result<Self, FontError> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| FontError::Toml(e.message().into()))?;
+
+        const FIELDS: [&str; 8] = [
+            "name",
+            "columns",
+            "rows",
+            "glyph_width",
+            "glyph_height",
+            "padding",
+            "spacing",
+            "codepage",
+        ];
+        if let Some(field) = table.keys().find(|k| !FIELDS.contains(&k.as_str())) {
+            return Err(FontError::UnknownField(field.clone()));
+        }
+
+        let text = |field: &str| match table.get(field) {
+            None => Ok(None),
+            Some(v) => v
+                .as_str()
+                .map(Some)
+                .ok_or_else(|| FontError::NotText(field.into())),
+        };
+        let number = |field: &str| match table.get(field) {
+            None => Ok(None),
+            Some(v) => v
+                .as_integer()
+                .and_then(|n| u16::try_from(n).ok())
+                .map(Some)
+                .ok_or_else(|| FontError::NotANumber(field.into())),
+        };
+
+        let codepage = match text("codepage")? {
+            None => None,
+            Some("cp437") => Some(Codepage::Cp437),
+            Some(other) => return Err(FontError::UnknownCodepage(other.into())),
+        };
+        Ok(Self {
+            name: text("name")?.unwrap_or(default_name).into(),
+            columns: number("columns")?.ok_or(FontError::Missing("columns"))?,
+            rows: number("rows")?.ok_or(FontError::Missing("rows"))?,
+            glyph_width: number("glyph_width")?,
+            glyph_height: number("glyph_height")?,
+            padding: number("padding")?.unwrap_or(0),
+            spacing: number("spacing")?.unwrap_or(0),
+            codepage,
+        })
+    }
+
+    /// Reads the metadata beside an image, if there is any
+    pub fn load(image: &str) -> Result<Option<Self>, SadieError> {
+        let path = metadata_path(image);
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text, &image_name(image))
+                .map(Some)
+                .map_err(|error| SadieError::BadFont { path, error }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(SadieError::Io(e)),
+        }
+    }
+}
+
+/// How long glyphs are along one side of the image, checking that they fill it exactly
+fn glyph_length(
+    dimension: &'static str,
+    pixels: i32,
+    glyphs: u16,
+    declared: Option<u16>,
+    font: &FontMetadata,
+) -> Result<i32, FontError> {
+    let glyphs_i = glyphs as i32;
+    let room = pixels - 2 * font.padding as i32 - (glyphs_i - 1) * font.spacing as i32;
+    match declared {
+        Some(length) if room != glyphs_i * length as i32 => Err(FontError::WrongSize {
+            dimension,
+            pixels,
+            needed: pixels - room + glyphs_i * length as i32,
+        }),
+        Some(length) => Ok(length as i32),
+        None if room <= 0 || room % glyphs_i != 0 => Err(FontError::Uneven {
+            dimension,
+            pixels,
+            glyphs,
+        }),
+        None => Ok(room / glyphs_i),
+    }
+}
+
 #[derive(Clone)]
 pub struct TextmodeFont {
     pub source: TextmodeFontSource,
     char_quads: HashMap<CharID, Rectangle>,
+    description: FontDescription,
+    name: String,
+    codepage: Option<Codepage>,
 }
 
 impl AsRef<raylib::ffi::Texture2D> for TextmodeFont {
@@ -83,76 +416,165 @@ impl Charset for TextmodeFont {
     fn len(&self) -> u16 {
         self.char_quads.len() as u16
     }
+
+    fn box_glyphs(&self) -> Option<BoxGlyphs> {
+        match self.codepage? {
+            Codepage::Cp437 => (self.len() >= 256).then_some(BoxGlyphs::CP437),
+        }
+    }
 }
 
 impl GuiCharset for TextmodeFont {
     fn get_char_size(&self) -> Size2D<u16> {
-        let (_, q) = self
-            .char_quads
-            .iter()
-            .next()
-            .expect("textmode font has no quads");
+        quad_size(&self.char_quads)
+    }
+}
+
+fn quad_size(char_quads: &HashMap<CharID, Rectangle>) -> Size2D<u16> {
+    let (_, q) = char_quads
+        .iter()
+        .next()
+        .expect("textmode font has no quads");
+
+    (q.width as u16, q.height as u16).into()
+}
+
+/// Makes the black of a font see-through, so that glyphs can be tinted and drawn
+/// over any background
+pub fn clear_black(image: &mut Image) {
+    image.set_format(PixelFormat::PIXELFORMAT_UNCOMPRESSED_R8G8B8A8);
+    image.color_replace(Color::BLACK, Color::BLANK);
+}
 
-        (q.width as u16, q.height as u16).into()
+/// Font images should only have the colors black and white
+fn check_black_and_white(image: &Image, fontname: &str) -> Result<(), SadieError> {
+    let palette = image.extract_palette(3);
+    if !(palette.len() == 2
+        && [Color::BLACK, Color::WHITE]
+            .into_iter()
+            .all(|item| palette.contains(&item)))
+    {
+        return Err(SadieError::NotBlackAndWhite {
+            fontname: fontname.into(),
+            palette,
+        });
     }
+    Ok(())
 }
 
 impl TextmodeFont {
+    /// Where each glyph is in an image laid out like `font` says
     fn make_char_quads(
-        texture_width: i32,
-        texture_height: i32,
-        columns: u16,
-        rows: u16,
-    ) -> HashMap<u16, Rectangle> {
-        let (cell_width, cell_height) =
-            (
-                { texture_width / columns as i32 } as f32,
-                { texture_height / rows as i32 } as f32,
-            );
-        (0..{ columns * rows })
-            .map(|i| {
-                let (x, y) = ({ i % columns } as f32, { i / columns } as f32);
+        image_size: Size2D<i32>,
+        font: &FontMetadata,
+    ) -> Result<HashMap<u16, Rectangle>, FontError> {
+        let (columns, rows) = (font.columns, font.rows);
+        if columns == 0 || rows == 0 {
+            return Err(FontError::NoGlyphs);
+        }
+        let count = columns as u32 * rows as u32;
+        if count > u16::MAX as u32 {
+            return Err(FontError::TooManyGlyphs(count));
+        }
 
+        let width = glyph_length("wide", image_size.width, columns, font.glyph_width, font)?;
+        let height = glyph_length("tall", image_size.height, rows, font.glyph_height, font)?;
+        let (padding, spacing) = (font.padding as i32, font.spacing as i32);
+        Ok((0..count as u16)
+            .map(|i| {
+                let (x, y) = ((i % columns) as i32, (i / columns) as i32);
                 (
                     i,
                     Rectangle {
-                        x: x * cell_width,
-                        y: y * cell_height,
-                        width: cell_width,
-                        height: cell_height,
+                        x: (padding + x * (width + spacing)) as f32,
+                        y: (padding + y * (height + spacing)) as f32,
+                        width: width as f32,
+                        height: height as f32,
                     },
                 )
             })
-            .collect()
+            .collect())
     }
 
-    pub fn load_charset(
+    /// Loads the font in an image, laid out like its description says
+    pub fn load(
         rl: &mut RaylibHandle,
         rt: &RaylibThread,
-        filename: &str,
-        columns: u16,
-        rows: u16,
+        description: &FontDescription,
     ) -> Result<Self, SadieError> {
-        let source =
-            TextmodeFontSource::new(rl.load_texture(rt, filename).map_err(SadieError::Raylib)?);
-        // should only have the colors black and white
-        let palette = source
-            .load_image()
-            .map_err(SadieError::Raylib)?
-            .extract_palette(3);
-        if !(palette.len() == 2
-            && [Color::BLACK, Color::WHITE]
-                .into_iter()
-                .all(|item| palette.contains(&item)))
-        {
-            return Err(SadieError::NotBlackAndWhite {
-                fontname: filename.into(),
-                palette,
-            });
-        }
+        let (path, metadata) = (&description.path, &description.metadata);
+        let mut image = Image::load_image(path).map_err(SadieError::Raylib)?;
+        check_black_and_white(&image, path)?;
+        let char_quads = Self::make_char_quads((image.width(), image.height()).into(), metadata)
+            .map_err(|error| SadieError::BadFont {
+                path: path.clone(),
+                error,
+            })?;
+
+        clear_black(&mut image);
+        let source = TextmodeFontSource::new(
+            rl.load_texture_from_image(rt, &image)
+                .map_err(SadieError::Raylib)?,
+        );
+
+        Ok(Self {
+            source,
+            char_quads,
+            description: description.clone(),
+            name: metadata.name.clone(),
+            codepage: metadata.codepage,
+        })
+    }
+
+    pub fn description(&self) -> &FontDescription {
+        &self.description
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A font kept in CPU memory rather than on the GPU, for drawing without a window
+#[derive(Clone)]
+pub struct ImageFont {
+    pub image: Image,
+    char_quads: HashMap<CharID, Rectangle>,
+}
+
+impl Charset for ImageFont {
+    type Item = Rectangle;
+    fn get_char(&self, id: CharID) -> Self::Item {
+        *self
+            .char_quads
+            .get(&id)
+            .expect("rectangle for this id not found")
+    }
+
+    fn len(&self) -> u16 {
+        self.char_quads.len() as u16
+    }
+}
+
+impl GuiCharset for ImageFont {
+    fn get_char_size(&self) -> Size2D<u16> {
+        quad_size(&self.char_quads)
+    }
+}
+
+impl ImageFont {
+    pub fn load(description: &FontDescription) -> Result<Self, SadieError> {
+        let (path, metadata) = (&description.path, &description.metadata);
+        let image = Image::load_image(path).map_err(SadieError::Raylib)?;
+        check_black_and_white(&image, path)?;
 
-        let char_quads = Self::make_char_quads(source.width(), source.height(), columns, rows);
+        let char_quads =
+            TextmodeFont::make_char_quads((image.width(), image.height()).into(), metadata)
+                .map_err(|error| SadieError::BadFont {
+                    path: path.clone(),
+                    error,
+                })?;
 
-        Ok(Self { source, char_quads })
+        Ok(Self { image, char_quads })
     }
 }
@@ -1,14 +1,18 @@
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::HashMap;
 
-use euclid::default::{Point2D, Size2D};
+use euclid::default::{Point2D, Size2D, Vector2D};
 use raylib::prelude::*;
 
 use crate::{
     core::{
-        canvas::{Canvas, Cursor},
+        actions::{Action, FillMatch, Mode},
+        canvas::{Anchor, Canvas, Cursor},
         gallery::Gallery,
+        layers::{Blend, LayerCommand, LayerError},
+        registers::Registers,
+        CanvasPos, CharID,
     },
-    gui::{font::TextmodeFont, palette::Palette, GuiCharset},
+    gui::{font::TextmodeFont, layout, layout::Dock, palette::Palette, GuiCharset},
     SadieError,
 };
 
@@ -16,6 +20,7 @@ use crate::{
 impl<T, C, A> Canvas<C, A>
 where
     C: GuiCharset<Item = T>,
+    A: Blend + Default + Clone + PartialEq,
 {
     fn make_render_texture(
         &self,
@@ -32,7 +37,7 @@ where
         F: FnMut(&mut Rd, Point2D<u16>, T, &A),
     {
         for (x, y, t, a) in self.cells() {
-            func(d, (x, y).into(), t, a);
+            func(d, (x, y).into(), t, &a);
         }
     }
 
@@ -47,11 +52,42 @@ where
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CellColors {
     pub fg: Color,
     pub bg: Color,
 }
 
+impl CellColors {
+    /// Whether two cells are alike enough for a flood fill to spread between them
+    pub fn matches(by: FillMatch, a: &(CharID, Self), b: &(CharID, Self)) -> bool {
+        match by {
+            FillMatch::Glyph => a.0 == b.0,
+            FillMatch::Foreground => a.1.fg == b.1.fg,
+            FillMatch::Background => a.1.bg == b.1.bg,
+            FillMatch::Cell => a == b,
+        }
+    }
+}
+
+impl Blend for CellColors {
+    fn blend(&self, below: &Self, opacity: f32) -> Self {
+        let mix = |above: Color, below: Color| {
+            let channel = |a: u8, b: u8| (b as f32 + (a as f32 - b as f32) * opacity).round() as u8;
+            Color::new(
+                channel(above.r, below.r),
+                channel(above.g, below.g),
+                channel(above.b, below.b),
+                channel(above.a, below.a),
+            )
+        };
+        Self {
+            fg: mix(self.fg, below.fg),
+            bg: mix(self.bg, below.bg),
+        }
+    }
+}
+
 impl Default for CellColors {
     fn default() -> Self {
         Self {
@@ -68,35 +104,182 @@ pub enum DrawableCanvas {
     ColorSquares(Canvas<Palette>),
 }
 
+/// Evaluates the same expression for whichever canvas is inside of a `DrawableCanvas`
+macro_rules! with_canvas {
+    ($drawable:expr, $canvas:ident => $body:expr) => {
+        match $drawable {
+            DrawableCanvas::ColoredFont($canvas) => $body,
+            DrawableCanvas::FontOnly($canvas) => $body,
+            DrawableCanvas::ColorSquares($canvas) => $body,
+        }
+    };
+}
+
+impl DrawableCanvas {
+    /// Only the user's art can be edited, pickers just move their cursors around
+    pub fn apply(&mut self, action: Action) {
+        match (self, action) {
+            (DrawableCanvas::ColoredFont(c), action) => c.apply(action),
+            (_, Action::Operate(..) | Action::Visual) => {}
+            (drawable, action) => with_canvas!(drawable, c => c.apply(action)),
+        }
+    }
+
+    /// Applies an action with registers, which only the user's art can yank into
+    pub fn apply_with(&mut self, action: Action, registers: &mut Registers<CellColors>) {
+        match self {
+            DrawableCanvas::ColoredFont(c) => c.apply_with(action, registers),
+            drawable => drawable.apply(action),
+        }
+    }
+
+    /// Makes a change with the brush, only the user's art is drawn on
+    pub fn apply_brush(&mut self, action: Action, brush: (CharID, CellColors)) {
+        if let DrawableCanvas::ColoredFont(c) = self {
+            c.apply_brush(action, brush, CellColors::matches);
+        }
+    }
+
+    /// Changes the layers of the user's art, pickers only ever have one
+    pub fn apply_layer(&mut self, command: LayerCommand) -> Result<(), LayerError> {
+        match self {
+            DrawableCanvas::ColoredFont(c) => c.apply_layer(command),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stamps a single cell without ending the undo step, for strokes of the mouse
+    pub fn stamp(&mut self, position: CanvasPos, brush: (CharID, CellColors)) {
+        if let DrawableCanvas::ColoredFont(c) = self {
+            c.put(position.x, position.y, brush);
+        }
+    }
+
+    pub fn commit(&mut self) {
+        with_canvas!(self, c => c.commit())
+    }
+
+    pub fn place_cursor(&mut self, index: usize, position: CanvasPos) {
+        with_canvas!(self, c => c.place_cursor(index, position))
+    }
+
+    pub fn mode(&self) -> Mode {
+        with_canvas!(self, c => c.mode())
+    }
+
+    /// Where the art's selection started, relative to its cursor
+    pub fn selection_offset(&self) -> Option<Vector2D<i32>> {
+        match self {
+            DrawableCanvas::ColoredFont(c) => c.selection_offset(),
+            _ => None,
+        }
+    }
+
+    /// Selects a block on the art for `.` to repeat a change on, pickers never select
+    pub fn reselect(&mut self, offset: Vector2D<i32>) {
+        if let DrawableCanvas::ColoredFont(c) = self {
+            c.reselect(offset);
+        }
+    }
+
+    fn cell_size(&self) -> Size2D<u16> {
+        with_canvas!(self, c => c.charset().get_char_size())
+    }
+
+    fn size(&self) -> Size2D<u16> {
+        with_canvas!(self, c => c.size())
+    }
+}
+
 /// Holds a canvas to draw, and it's position to be rendered at
 pub struct Frame {
     pub position: Point2D<i32>,
+    /// How the layout places the frame
+    pub dock: Dock,
     contents: DrawableCanvas,
     render_texture: RenderTexture2D,
 }
 
 impl Frame {
+    pub fn apply(&mut self, action: Action) {
+        self.contents.apply(action);
+    }
+
+    pub fn apply_with(&mut self, action: Action, registers: &mut Registers<CellColors>) {
+        self.contents.apply_with(action, registers);
+    }
+
+    pub fn apply_brush(&mut self, action: Action, brush: (CharID, CellColors)) {
+        self.contents.apply_brush(action, brush);
+    }
+
+    pub fn apply_layer(&mut self, command: LayerCommand) -> Result<(), LayerError> {
+        self.contents.apply_layer(command)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.contents.mode()
+    }
+
+    /// Whether the frame holds the user's art, the only canvas changes are made to
+    pub fn is_art(&self) -> bool {
+        matches!(self.contents, DrawableCanvas::ColoredFont(_))
+    }
+
+    pub fn selection_offset(&self) -> Option<Vector2D<i32>> {
+        self.contents.selection_offset()
+    }
+
+    pub fn reselect(&mut self, offset: Vector2D<i32>) {
+        self.contents.reselect(offset);
+    }
+
+    /// The cell of the canvas under a point on the screen
+    pub fn cell_at(&self, point: Point2D<i32>) -> Option<CanvasPos> {
+        layout::cell_at(
+            self.position,
+            self.contents.size(),
+            self.contents.cell_size(),
+            point,
+        )
+    }
+
+    pub fn contents_mut(&mut self) -> &mut DrawableCanvas {
+        &mut self.contents
+    }
+
+    /// Where the frame is drawn on the screen
+    pub fn bounds(&self) -> Rectangle {
+        Rectangle::new(
+            self.position.x as f32,
+            self.position.y as f32,
+            self.render_texture.width() as f32,
+            self.render_texture.height() as f32,
+        )
+    }
+
     fn draw<Rd>(&mut self, d: &mut Rd, rt: &RaylibThread)
     where
         Rd: RaylibDraw + RaylibTextureModeExt,
     {
         {
             let mut rd = d.begin_texture_mode(rt, &mut self.render_texture);
+            rd.clear_background(Color::BLACK);
             match &self.contents {
                 DrawableCanvas::ColoredFont(c) => {
-                    c.draw_cells_mode(&mut rd, |rdd, p, r, a| {
-                        rdd.draw_texture_rec(
-                            c.charset(),
-                            r,
-                            Vector2 {
-                                x: p.x as f32 * r.width,
-                                y: p.y as f32 * r.height,
-                            },
-                            Color::WHITE,
-                        );
+                    c.draw_cells_mode(&mut rd, |rdd, p, r, colors| {
+                        let position = Vector2 {
+                            x: p.x as f32 * r.width,
+                            y: p.y as f32 * r.height,
+                        };
+                        // fonts are loaded with black made transparent, so the
+                        // background shows around the tinted glyph
+                        rdd.draw_rectangle_v(position, Vector2::new(r.width, r.height), colors.bg);
+                        rdd.draw_texture_rec(c.charset(), r, position, colors.fg);
                     });
 
                     let s = c.charset().get_char_size();
+                    c.draw_cursors_mode(&mut rd, |rdd, c| draw_selection(rdd, c, s));
                     c.draw_cursors_mode(&mut rd, |rdd, c| draw_x_cursor(rdd, c, s));
                 }
                 DrawableCanvas::FontOnly(c) => {
@@ -128,10 +311,15 @@ impl Frame {
             }
         }
 
-        d.draw_texture(
+        // render textures are stored upside down
+        let (w, h) = (
+            self.render_texture.width() as f32,
+            self.render_texture.height() as f32,
+        );
+        d.draw_texture_rec(
             &self.render_texture,
-            self.position.x,
-            self.position.y,
+            Rectangle::new(0., 0., w, -h),
+            Vector2::new(self.position.x as f32, self.position.y as f32),
             Color::WHITE,
         );
     }
@@ -146,6 +334,22 @@ fn draw_x_cursor(d: &mut impl RaylibDraw, c: &Cursor, size: Size2D<u16>) {
     d.draw_line(start.x, end.y, end.x, start.y, Color::RED);
 }
 
+/// Shades the block of cells a cursor is selecting
+fn draw_selection(d: &mut impl RaylibDraw, c: &Cursor, size: Size2D<u16>) {
+    let Some(selection) = c.selection() else {
+        return;
+    };
+    let (w, h) = (size.width as f32, size.height as f32);
+    let area = Rectangle::new(
+        selection.min.x as f32 * w,
+        selection.min.y as f32 * h,
+        selection.width() as f32 * w,
+        selection.height() as f32 * h,
+    );
+    d.draw_rectangle_rec(area, Color::SKYBLUE.alpha(0.4));
+    d.draw_rectangle_lines_ex(area, 1., Color::SKYBLUE);
+}
+
 impl
     TryFrom<(
         (&mut RaylibHandle, &RaylibThread),
@@ -166,6 +370,7 @@ impl
 
         Ok(Self {
             position: Point2D::zero(),
+            dock: Dock::default(),
             contents: DrawableCanvas::ColoredFont(canvas),
             render_texture,
         })
@@ -184,6 +389,7 @@ impl TryFrom<((&mut RaylibHandle, &RaylibThread), Canvas<TextmodeFont>)> for Fra
 
         Ok(Self {
             position: Point2D::zero(),
+            dock: Dock::default(),
             contents: DrawableCanvas::FontOnly(canvas),
             render_texture,
         })
@@ -202,42 +408,175 @@ impl TryFrom<((&mut RaylibHandle, &RaylibThread), Canvas<Palette>)> for Frame {
 
         Ok(Self {
             position: Point2D::zero(),
+            dock: Dock::default(),
             contents: DrawableCanvas::ColorSquares(canvas),
             render_texture,
         })
     }
 }
 
-type CID = u32;
+pub type CID = u32;
 pub struct GuiGallery {
     id_base: CID,
     frames: HashMap<CID, Frame>,
+    /// Frames from the bottom of the stack to the top, which is the order they're drawn in
+    z_order: Vec<CID>,
+    /// The frame that receives actions, starting with the first one added
+    focus: Option<CID>,
 }
 
 impl GuiGallery {
+    /// Outlines the frame that receives actions
+    const FOCUS_COLOR: Color = Color::ORANGE;
+
     pub fn new() -> Self {
         Self {
             id_base: 1,
             frames: HashMap::new(),
+            z_order: vec![],
+            focus: None,
         }
     }
 
     fn pick_id(&mut self) -> CID {
         let id = self.id_base;
         self.id_base += 1;
+        self.focus.get_or_insert(id);
+        id
+    }
+
+    /// Puts a frame on top of every other one
+    fn add(&mut self, frame: Frame, dock: Dock) -> CID {
+        let id = self.pick_id();
+        self.frames.insert(id, Frame { dock, ..frame });
+        self.z_order.push(id);
         id
     }
 
+    /// Every frame's id in the order they were added
+    fn ids(&self) -> Vec<CID> {
+        let mut ids: Vec<CID> = self.frames.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Moves a frame to the top of the stack
+    pub fn raise(&mut self, id: CID) {
+        self.z_order.retain(|&z| z != id);
+        if self.frames.contains_key(&id) {
+            self.z_order.push(id);
+        }
+    }
+
+    /// Places every frame on a screen of this size, see [`layout::arrange`]
+    pub fn layout(&mut self, screen: Size2D<i32>) {
+        let ids = self.ids();
+        let frames: Vec<_> = ids
+            .iter()
+            .map(|id| {
+                let b = self.frames[id].bounds();
+                (
+                    self.frames[id].dock,
+                    Size2D::new(b.width as i32, b.height as i32),
+                )
+            })
+            .collect();
+
+        for (id, position) in ids.iter().zip(layout::arrange(screen, &frames)) {
+            if let Some(frame) = self.frames.get_mut(id) {
+                frame.position = position;
+            }
+        }
+    }
+
+    pub fn focused(&self) -> Option<&Frame> {
+        self.focus.and_then(|id| self.frames.get(&id))
+    }
+
+    pub fn focused_mut(&mut self) -> Option<&mut Frame> {
+        self.focus.and_then(|id| self.frames.get_mut(&id))
+    }
+
+    pub fn get_mut(&mut self, id: CID) -> Option<&mut Frame> {
+        self.frames.get_mut(&id)
+    }
+
+    /// Gives a frame focus and brings it to the top
+    pub fn focus(&mut self, id: CID) {
+        if self.frames.contains_key(&id) {
+            self.focus = Some(id);
+            self.raise(id);
+        }
+    }
+
+    /// The topmost frame under a point on the screen, and the cell under it
+    pub fn hit(&self, point: Point2D<i32>) -> Option<(CID, CanvasPos)> {
+        self.z_order.iter().rev().find_map(|id| {
+            let cell = self.frames.get(id)?.cell_at(point)?;
+            Some((*id, cell))
+        })
+    }
+
+    /// Moves focus through the frames in the order they were added, wrapping
+    /// around at either end
+    pub fn cycle_focus(&mut self, steps: i32) {
+        let ids = self.ids();
+        let Some(current) = self.focus.and_then(|f| ids.iter().position(|&id| id == f)) else {
+            self.focus = ids.first().copied();
+            return;
+        };
+
+        let next = ids[(current as i64 + steps as i64).rem_euclid(ids.len() as i64) as usize];
+        self.focus = Some(next);
+        self.raise(next);
+    }
+
+    /// Swaps out what a frame shows, the gallery should be laid out again after
+    pub fn replace(
+        &mut self,
+        rl: &mut RaylibHandle,
+        rt: &RaylibThread,
+        id: CID,
+        contents: DrawableCanvas,
+    ) -> Result<(), SadieError> {
+        let Some(frame) = self.frames.get_mut(&id) else {
+            return Ok(());
+        };
+        frame.render_texture = with_canvas!(&contents, c => c.make_render_texture(rl, rt))?;
+        frame.contents = contents;
+        Ok(())
+    }
+
+    /// Resizes the art in a frame and gives it a texture to match, the gallery
+    /// should be laid out again after
+    pub fn resize(
+        &mut self,
+        rl: &mut RaylibHandle,
+        rt: &RaylibThread,
+        id: CID,
+        size: Size2D<u16>,
+        anchor: Anchor,
+        fill: (CharID, CellColors),
+    ) -> Result<(), SadieError> {
+        let Some(frame) = self.frames.get_mut(&id) else {
+            return Ok(());
+        };
+        if let DrawableCanvas::ColoredFont(c) = &mut frame.contents {
+            c.resize(size, anchor, fill);
+            frame.render_texture = c.make_render_texture(rl, rt)?;
+        }
+        Ok(())
+    }
+
     pub fn add_colored_font(
         &mut self,
         rl: &mut RaylibHandle,
         rt: &RaylibThread,
         canvas: Canvas<TextmodeFont, CellColors>,
-    ) -> Result<Entry<'_, CID, Frame>, SadieError> {
-        let id = self.pick_id();
+        dock: Dock,
+    ) -> Result<CID, SadieError> {
         let frame = ((rl, rt), canvas).try_into()?;
-        self.frames.insert(id, frame);
-        Ok(self.frames.entry(id))
+        Ok(self.add(frame, dock))
     }
 
     pub fn add_font_only(
@@ -245,11 +584,10 @@ impl GuiGallery {
         rl: &mut RaylibHandle,
         rt: &RaylibThread,
         canvas: Canvas<TextmodeFont>,
-    ) -> Result<Entry<'_, CID, Frame>, SadieError> {
-        let id = self.pick_id();
+        dock: Dock,
+    ) -> Result<CID, SadieError> {
         let frame = ((rl, rt), canvas).try_into()?;
-        self.frames.insert(id, frame);
-        Ok(self.frames.entry(id))
+        Ok(self.add(frame, dock))
     }
 
     pub fn add_color_squares(
@@ -257,16 +595,23 @@ impl GuiGallery {
         rl: &mut RaylibHandle,
         rt: &RaylibThread,
         canvas: Canvas<Palette>,
-    ) -> Result<Entry<'_, CID, Frame>, SadieError> {
-        let id = self.pick_id();
+        dock: Dock,
+    ) -> Result<CID, SadieError> {
         let frame = ((rl, rt), canvas).try_into()?;
-        self.frames.insert(id, frame);
-        Ok(self.frames.entry(id))
+        Ok(self.add(frame, dock))
     }
 
     pub fn draw<Rd: RaylibDraw + RaylibTextureModeExt>(&mut self, d: &mut Rd, rt: &RaylibThread) {
-        for (_cid, frame) in self.frames.iter_mut() {
-            frame.draw(d, rt);
+        for id in self.z_order.iter() {
+            if let Some(frame) = self.frames.get_mut(id) {
+                frame.draw(d, rt);
+            }
+        }
+
+        if let Some(frame) = self.focus.and_then(|id| self.frames.get(&id)) {
+            let b = frame.bounds();
+            let outline = Rectangle::new(b.x - 3., b.y - 3., b.width + 6., b.height + 6.);
+            d.draw_rectangle_lines_ex(outline, 2., Self::FOCUS_COLOR);
         }
     }
 }
@@ -0,0 +1,161 @@
+use euclid::default::{Box2D, Point2D, Size2D};
+
+use crate::core::CanvasPos;
+
+/// Where the layout keeps a frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dock {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    /// Placed in rows across whatever space the docked frames leave
+    #[default]
+    Float,
+}
+
+/// Space kept between frames and around the edge of the screen
+const MARGIN: i32 = 8;
+const GAP: i32 = 8;
+
+/// Finds where every frame goes on a screen, given how it's docked and how big it is.
+///
+/// Docked frames are placed first, in order. Each one is pushed against its edge
+/// of the free space and takes a strip of it, so later frames on the same edge
+/// stack inwards. Floating frames then fill the free space left to right,
+/// wrapping onto a new row when one doesn't fit.
+pub fn arrange(screen: Size2D<i32>, frames: &[(Dock, Size2D<i32>)]) -> Vec<Point2D<i32>> {
+    let mut free = Box2D::new(
+        Point2D::new(MARGIN, MARGIN),
+        Point2D::new(screen.width - MARGIN, screen.height - MARGIN),
+    );
+    let mut positions = vec![Point2D::zero(); frames.len()];
+
+    for (position, (dock, size)) in positions.iter_mut().zip(frames) {
+        *position = match dock {
+            Dock::Left => {
+                let p = free.min;
+                free.min.x += size.width + GAP;
+                p
+            }
+            Dock::Right => {
+                free.max.x -= size.width;
+                let p = Point2D::new(free.max.x, free.min.y);
+                free.max.x -= GAP;
+                p
+            }
+            Dock::Top => {
+                let p = free.min;
+                free.min.y += size.height + GAP;
+                p
+            }
+            Dock::Bottom => {
+                free.max.y -= size.height;
+                let p = Point2D::new(free.min.x, free.max.y);
+                free.max.y -= GAP;
+                p
+            }
+            Dock::Float => continue,
+        };
+    }
+
+    let mut next = free.min;
+    let mut row_height = 0;
+    for (position, (_, size)) in positions
+        .iter_mut()
+        .zip(frames)
+        .filter(|(_, (dock, _))| *dock == Dock::Float)
+    {
+        if next.x > free.min.x && next.x + size.width > free.max.x {
+            next = Point2D::new(free.min.x, next.y + row_height + GAP);
+            row_height = 0;
+        }
+        *position = next;
+        next.x += size.width + GAP;
+        row_height = row_height.max(size.height);
+    }
+
+    positions
+}
+
+/// Which cell a point lands on, for a grid of `cells` drawn at `origin` with
+/// each cell `cell_size` pixels big
+pub fn cell_at(
+    origin: Point2D<i32>,
+    cells: Size2D<u16>,
+    cell_size: Size2D<u16>,
+    point: Point2D<i32>,
+) -> Option<CanvasPos> {
+    let offset = point - origin;
+    if offset.x < 0 || offset.y < 0 || cell_size.is_empty() {
+        return None;
+    }
+
+    let x = offset.x / cell_size.width as i32;
+    let y = offset.y / cell_size.height as i32;
+    (x < cells.width as i32 && y < cells.height as i32).then(|| CanvasPos::new(x as u16, y as u16))
+}
+
+#[cfg(test)]
+mod layout_test {
+    use super::*;
+
+    #[test]
+    fn docking() {
+        let frames = [
+            (Dock::Bottom, Size2D::new(300, 16)),
+            (Dock::Bottom, Size2D::new(128, 8)),
+            (Dock::Left, Size2D::new(50, 100)),
+            (Dock::Right, Size2D::new(40, 40)),
+            (Dock::Top, Size2D::new(10, 10)),
+        ];
+        let positions = arrange(Size2D::new(400, 300), &frames);
+        assert_eq!(
+            positions,
+            vec![
+                Point2D::new(8, 276),
+                Point2D::new(8, 260),
+                Point2D::new(8, 8),
+                Point2D::new(352, 8),
+                Point2D::new(66, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn floating_frames_wrap() {
+        let frames = [
+            (Dock::Float, Size2D::new(100, 20)),
+            (Dock::Left, Size2D::new(50, 50)),
+            (Dock::Float, Size2D::new(100, 40)),
+            (Dock::Float, Size2D::new(100, 10)),
+        ];
+        let positions = arrange(Size2D::new(300, 300), &frames);
+        assert_eq!(
+            positions,
+            vec![
+                Point2D::new(66, 8),
+                Point2D::new(8, 8),
+                Point2D::new(174, 8),
+                Point2D::new(66, 56),
+            ]
+        );
+    }
+
+    #[test]
+    fn hitting_cells() {
+        let hit = |x, y| {
+            cell_at(
+                Point2D::new(10, 20),
+                Size2D::new(4, 3),
+                Size2D::new(8, 8),
+                Point2D::new(x, y),
+            )
+        };
+        assert_eq!(hit(10, 20), Some(CanvasPos::new(0, 0)));
+        assert_eq!(hit(35, 30), Some(CanvasPos::new(3, 1)));
+        assert_eq!(hit(42, 30), None);
+        assert_eq!(hit(9, 30), None);
+        assert_eq!(hit(12, 44), None);
+    }
+}
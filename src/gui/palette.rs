@@ -20,6 +20,10 @@ impl Charset for Palette {
 }
 
 impl Palette {
+    pub fn colors(&self) -> &[Color] {
+        &self.0
+    }
+
     pub const PICO8: [Color; 16] = [
         Color {
             r: 0,
@@ -126,6 +130,12 @@ impl<const N: usize> From<[Color; N]> for Palette {
     }
 }
 
+impl From<Vec<Color>> for Palette {
+    fn from(colors: Vec<Color>) -> Self {
+        Self(colors, (8, 8).into())
+    }
+}
+
 impl GuiCharset for Palette {
     fn get_char_size(&self) -> Size2D<u16> {
         self.1
@@ -0,0 +1,380 @@
+use std::collections::VecDeque;
+
+use crate::{
+    core::{
+        actions::{Action, ActionError, FillMatch, Mode},
+        canvas::Canvas,
+        command::{Command, CommandError},
+        cp437::Cp437,
+        input::{InputEvent, KeyEvent},
+        layers::Blend,
+        registers::Registers,
+        repeat::LastChange,
+        CharID, Charset,
+    },
+    SadieContext, SadieError,
+};
+
+/// Runs sadie without a display. Keys come from a script instead of a keyboard,
+/// and the context stays alive until every key has been read.
+pub struct HeadlessContext<C = Cp437, A = ()> {
+    keys: VecDeque<KeyEvent>,
+    canvas: Canvas<C, A>,
+    registers: Registers<A>,
+    /// What painting stamps, there are no pickers to choose another
+    brush: (CharID, A),
+    last_change: LastChange<()>,
+    errors: Vec<ActionError>,
+}
+
+impl<C, A: Default> HeadlessContext<C, A> {
+    pub fn new(canvas: Canvas<C, A>, keys: impl IntoIterator<Item = KeyEvent>) -> Self {
+        Self {
+            keys: keys.into_iter().collect(),
+            canvas,
+            registers: Registers::default(),
+            brush: (b'#' as CharID, A::default()),
+            last_change: LastChange::default(),
+            errors: vec![],
+        }
+    }
+
+    pub fn canvas(&self) -> &Canvas<C, A> {
+        &self.canvas
+    }
+
+    /// Every key sequence that was rejected so far
+    pub fn errors(&self) -> &[ActionError] {
+        &self.errors
+    }
+}
+
+impl<T, C, A> HeadlessContext<C, A>
+where
+    C: Charset<Item = T>,
+    A: Blend + Default + Clone + PartialEq,
+{
+    /// The characters on the canvas, row by row, as they look with the layers
+    /// stacked up
+    pub fn grid(&self) -> Vec<Vec<T>> {
+        let size = self.canvas.size();
+        (0..size.height)
+            .map(|y| {
+                (0..size.width)
+                    .map(|x| {
+                        self.canvas
+                            .charset()
+                            .get_char(self.canvas.composite(x, y).0)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl<T, C, A> SadieContext for HeadlessContext<C, A>
+where
+    C: Charset<Item = T>,
+    A: Blend + Default + Clone + PartialEq,
+{
+    fn check_input(&mut self) -> Option<InputEvent> {
+        self.keys.pop_front().map(InputEvent::Key)
+    }
+
+    fn is_alive(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    fn draw(&mut self) {}
+
+    fn mode(&self) -> Mode {
+        self.canvas.mode()
+    }
+
+    fn apply_actions(&mut self, action: Action) -> Result<(), SadieError> {
+        let action = match action {
+            Action::Command(command) => {
+                self.command(command);
+                return Ok(());
+            }
+            Action::Repeat(count) => match self.last_change.replay(count) {
+                Some(change) => {
+                    if let Some(offset) = change.selection {
+                        self.canvas.reselect(offset);
+                    }
+                    change.action
+                }
+                None => return Ok(()),
+            },
+            action => action,
+        };
+        self.last_change
+            .record(&action, self.canvas.selection_offset(), ());
+        if action.needs_brush() {
+            self.canvas
+                .apply_brush(action, self.brush.clone(), matches_cells);
+        } else {
+            self.canvas.apply_with(action, &mut self.registers);
+        }
+        Ok(())
+    }
+
+    fn report_error(&mut self, error: ActionError) {
+        self.errors.push(error);
+    }
+}
+
+impl<T, C, A> HeadlessContext<C, A>
+where
+    C: Charset<Item = T>,
+    A: Blend + Default + Clone + PartialEq,
+{
+    /// Carries out a command, reporting the ones that need files as errors
+    /// since a script has nowhere to keep them
+    fn command(&mut self, command: Command) {
+        let (name, result) = match command {
+            Command::Write(_) => ("w", Err(CommandError::NoFiles("w"))),
+            Command::Edit(_) => ("e", Err(CommandError::NoFiles("e"))),
+            Command::Export { .. } => ("export", Err(CommandError::NoFiles("export"))),
+            Command::Resize { size, anchor } => {
+                self.canvas.resize(size, anchor, (0, A::default()));
+                ("resize", Ok(()))
+            }
+            Command::Layer(command) => (
+                "layer",
+                self.canvas
+                    .apply_layer(command)
+                    .map_err(CommandError::Layer),
+            ),
+        };
+        if let Err(error) = result {
+            let buffer = format!(":{name}");
+            self.errors.push(ActionError::BadCommand {
+                error,
+                span: 0..buffer.len(),
+                buffer,
+            });
+        }
+    }
+}
+
+/// Cells here have nothing but their glyph to tell them apart by, so flood
+/// fills by colour spread over cells that are the same in every way
+fn matches_cells<A: PartialEq>(by: FillMatch, a: &(CharID, A), b: &(CharID, A)) -> bool {
+    match by {
+        FillMatch::Glyph => a.0 == b.0,
+        FillMatch::Foreground | FillMatch::Background | FillMatch::Cell => a == b,
+    }
+}
+
+#[cfg(test)]
+mod headless_test {
+    use super::*;
+    use crate::{
+        core::{canvas::CanvasBuilder, input::parse_keys, keymap::Keymap},
+        run,
+    };
+
+    fn script(keys: &str) -> HeadlessContext {
+        let canvas = CanvasBuilder::init(Cp437)
+            .size((8, 4).into())
+            .default_cells(|id, _| (b'a' as u16 + id % 8, ()))
+            .build();
+        let mut context = HeadlessContext::new(canvas, parse_keys(keys));
+        run(&mut context, Keymap::default()).unwrap();
+        context
+    }
+
+    fn cursor(context: &HeadlessContext) -> (u16, u16) {
+        context.canvas().active_cursor().unwrap().position().into()
+    }
+
+    #[test]
+    fn moving_around() {
+        assert_eq!(cursor(&script("3lj")), (3, 1));
+        assert_eq!(cursor(&script("20lG")), (7, 3));
+        assert_eq!(cursor(&script("Gkgg")), (0, 0));
+        assert_eq!(cursor(&script("2<Right><Down>")), (2, 1));
+        assert_eq!(cursor(&script("3<Esc>l")), (1, 0));
+    }
+
+    #[test]
+    fn editing() {
+        let context = script("jddk2ld2l");
+        let rows: Vec<String> = context.grid().iter().map(|r| r.iter().collect()).collect();
+        assert_eq!(rows[0], "ab  efgh");
+        assert_eq!(rows[1], "        ");
+        assert_eq!(rows[2], "abcdefgh");
+
+        let context = script("jddu");
+        assert_eq!(context.grid()[1].iter().collect::<String>(), "abcdefgh");
+    }
+
+    #[test]
+    fn selecting() {
+        let context = script("lvjld");
+        let rows: Vec<String> = context.grid().iter().map(|r| r.iter().collect()).collect();
+        assert_eq!(rows[0], "a  defgh");
+        assert_eq!(rows[1], "a  defgh");
+        assert_eq!(rows[2], "abcdefgh");
+        assert_eq!(cursor(&context), (1, 0));
+
+        // without a selection `d` waits for a motion again
+        assert_eq!(
+            script("v<Esc>dl").grid()[0].iter().collect::<String>(),
+            " bcdefgh"
+        );
+        assert_eq!(
+            script("vvdl").grid()[0].iter().collect::<String>(),
+            " bcdefgh"
+        );
+    }
+
+    #[test]
+    fn painting() {
+        let rows = |c: HeadlessContext| -> Vec<String> {
+            c.grid().iter().map(|r| r.iter().collect()).collect()
+        };
+        assert_eq!(rows(script("l2 "))[0], "a##defgh");
+        assert_eq!(
+            rows(script("vjl "))[..3],
+            ["##cdefgh", "##cdefgh", "abcdefgh"]
+        );
+        assert_eq!(rows(script("lfg")), ["a#cdefgh"; 4]);
+
+        let context = script("vj3lsr");
+        assert_eq!(rows(context)[..3], ["####efgh", "####efgh", "abcdefgh"]);
+        let context = script("ljv2lsl");
+        assert_eq!(rows(context)[..2], ["abcdefgh", "a###efgh"]);
+    }
+
+    #[test]
+    fn layers() {
+        let row = |c: HeadlessContext| c.grid()[0].iter().collect::<String>();
+        // paint on a new layer, then delete a cell of the one under it
+        let keys = "l:layer new<CR> l:layer 1<CR>dl";
+        assert_eq!(row(script(keys)), "a# defgh");
+
+        let hidden = format!("{keys}:layer 2<CR>:layer hide<CR>");
+        let context = script(&hidden);
+        assert!(context.errors().is_empty());
+        assert_eq!(row(context), "ab defgh");
+    }
+
+    #[test]
+    fn registers() {
+        let context = script("\"ay2lj\"ap");
+        assert_eq!(context.grid()[1].iter().collect::<String>(), "aabdefgh");
+
+        let context = script("vjly2j3lP");
+        let rows: Vec<String> = context.grid().iter().map(|r| r.iter().collect()).collect();
+        assert_eq!(rows[2], "abcabfgh");
+        assert_eq!(rows[3], "abcabfgh");
+    }
+
+    #[test]
+    fn repeating() {
+        let rows = |c: HeadlessContext| -> Vec<String> {
+            c.grid().iter().map(|r| r.iter().collect()).collect()
+        };
+        let context = script("d2lj.j3.");
+        assert_eq!(rows(context)[..3], ["  cdefgh", "  cdefgh", "   defgh"]);
+
+        // moving doesn't replace the change, and undo steps are kept apart
+        assert_eq!(rows(script("dlllj.u"))[..2], [" bcdefgh", "abcdefgh"]);
+
+        // a selection is repeated as the same sized block at the cursor
+        let context = script("lvjldjj.");
+        assert_eq!(
+            rows(context),
+            ["a  defgh", "a  defgh", "a  defgh", "a  defgh"]
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let context = script("qadljq2@a");
+        let rows: Vec<String> = context.grid().iter().map(|r| r.iter().collect()).collect();
+        assert_eq!(rows[..3], [" bcdefgh", " bcdefgh", " bcdefgh"]);
+
+        // `@@` plays the last macro again, and keys on the command line are recorded
+        let context = script("qb:layer show<CR>lqj@b@@");
+        assert!(context.errors().is_empty());
+        assert_eq!(cursor(&context), (3, 1));
+
+        // an error stops the macro playing
+        let context = script("qcdlQlq@c");
+        assert_eq!(context.errors().len(), 2);
+        assert_eq!(context.grid()[0].iter().collect::<String>(), "  cdefgh");
+
+        // a macro that plays itself is stopped rather than playing forever
+        let context = script("qa@aq@al");
+        assert_eq!(context.errors().len(), 1);
+        assert_eq!(context.errors()[0].buffer(), "@a");
+        assert_eq!(cursor(&context), (1, 0));
+    }
+
+    #[test]
+    fn keymaps() {
+        let keymap = Keymap::parse(
+            r#"
+            [normal]
+            n = "left"
+            i = "right"
+            "<Space>x" = "delete"
+            [visual]
+            x = "delete"
+            "#,
+        )
+        .unwrap();
+        let canvas = CanvasBuilder::init(Cp437)
+            .size((8, 1).into())
+            .default_cells(|id, _| (b'a' as u16 + id, ()))
+            .build();
+        // `"n` still names register n, and `l` moves right since it isn't rebound,
+        // which leaves the space before it to paint
+        let keys = parse_keys("3in<Space>xi\"nyl<Space>lvlx<Space>");
+        let mut context = HeadlessContext::new(canvas, keys);
+        run(&mut context, keymap).unwrap();
+
+        assert!(context.errors().is_empty());
+        assert_eq!(context.grid()[0].iter().collect::<String>(), "ab#  fgh");
+        assert_eq!(cursor(&context), (3, 0));
+    }
+
+    #[test]
+    fn command_line() {
+        // keys typed on the command line don't move the cursor
+        assert_eq!(cursor(&script(":ll<Esc>l")), (1, 0));
+
+        let context = script(":e<CR>l");
+        assert_eq!(context.errors().len(), 1);
+        assert_eq!(context.errors()[0].buffer(), ":e");
+        assert_eq!(cursor(&context), (1, 0));
+
+        let context = script("G7l:resize 4 2 se<CR>");
+        assert!(context.errors().is_empty());
+        assert_eq!(context.canvas().size(), (4, 2).into());
+        assert_eq!(context.grid()[1].iter().collect::<String>(), "efgh");
+        assert_eq!(cursor(&context), (3, 1));
+
+        // deleting the only layer can't be done, and there are no files to write
+        let context = script(":layer delete<CR>:w art.sadie<CR>");
+        let errors: Vec<_> = context.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "can't delete the only layer",
+                ":w works on files, which there are none of here"
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_keys_are_reported() {
+        let context = script("3Ql");
+        assert_eq!(context.errors().len(), 1);
+        assert_eq!(context.errors()[0].buffer(), "3Q");
+        assert_eq!(cursor(&context), (1, 0));
+    }
+}
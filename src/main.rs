@@ -1,16 +1,58 @@
+use std::{collections::VecDeque, env, fs, io::ErrorKind, path::PathBuf};
+
 use clap::Parser;
 use raylib::prelude::*;
 use thiserror::Error;
 
-use crate::{core::actions::{Action, parse_action}, gui::RaylibContext};
+use crate::{
+    core::{
+        actions::{parse_action, takes_argument, Action, ActionError, Mode},
+        canvas::CanvasBuilder,
+        command_line::CommandLine,
+        cp437::Cp437,
+        input::{parse_keys, InputEvent, KeyEvent, MouseEvent},
+        keymap::{Keymap, KeymapError, Lookup},
+        layers::LayerError,
+        macros::Macros,
+    },
+    document::{Document, DocumentError},
+    gui::{export_document, FontError, RaylibContext},
+    headless::HeadlessContext,
+    tui::TerminalContext,
+};
 
 mod core;
+mod document;
 mod gui;
+mod headless;
+mod tui;
 
 #[derive(Parser)]
 struct Args {
     #[arg(long)]
     tui: bool,
+
+    /// Types these keys onto a blank canvas without opening a window, then prints it.
+    /// Special keys are written like `<Esc>` or `<C-r>`
+    #[arg(long)]
+    script: Option<String>,
+
+    /// Renders a sadie file to a PNG beside it without opening a window
+    #[arg(long, value_name = "FILE")]
+    export: Option<PathBuf>,
+
+    /// How many pixels wide each pixel of the font becomes when exporting
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    scale: u16,
+
+    /// The image font the window draws with. How its glyphs are laid out is read
+    /// from a TOML file of the same name beside it
+    #[arg(long, value_name = "FILE", default_value = "gloop_8x8.png")]
+    font: String,
+
+    /// Rebinds keys from a TOML file, instead of `sadie/keymap.toml` in the config directory
+    #[arg(long, value_name = "FILE")]
+    keymap: Option<PathBuf>,
 }
 
 #[derive(Error, Debug)]
@@ -32,26 +74,195 @@ in {:?}",
 
     #[error("Raylib: {0:?}")]
     Raylib(raylib::core::error::Error),
+
+    #[error("IO: {0}")]
+    Io(std::io::Error),
+
+    #[error("Cannot open {path:?}: {error}")]
+    BadDocument { path: String, error: DocumentError },
+
+    #[error("No file name, give one like `:w art.sadie`")]
+    NoFileName,
+
+    #[error("No font to draw with, open a file that was saved with one")]
+    NoFont,
+
+    #[error("{0}")]
+    Layer(LayerError),
+
+    #[error("Cannot load font {path:?}: {error}")]
+    BadFont { path: String, error: FontError },
+
+    #[error("Cannot load keymap {path:?}: {error}")]
+    BadKeymap { path: String, error: KeymapError },
 }
 
 /// Something controls the flow and drawing of sadie
 pub trait SadieContext {
-    fn check_input(&mut self) -> Option<char>;
+    fn check_input(&mut self) -> Option<InputEvent>;
     fn is_alive(&self) -> bool;
     fn draw(&mut self);
     fn apply_actions(&mut self, action: Action) -> Result<(), SadieError>;
+
+    /// What the keys typed next mean, depending on what the focused canvas is doing
+    fn mode(&self) -> Mode {
+        Mode::Normal
+    }
+
+    /// Mice work on whatever is under them rather than going through actions
+    fn handle_mouse(&mut self, _event: MouseEvent) -> Result<(), SadieError> {
+        Ok(())
+    }
+
+    /// Tells the user why their keys were thrown away
+    fn report_error(&mut self, error: ActionError) {
+        eprintln!("E: {error}");
+    }
+
+    /// Shows the command line as it's typed, or hides it once it's closed
+    fn show_command_line(&mut self, _line: Option<&CommandLine>) {}
+
+    /// Shows which register a macro is being recorded into, if any
+    fn show_recording(&mut self, _register: Option<char>) {}
+}
+
+/// Turns keys into actions for a context, holding on to what's been typed so far
+#[derive(Default)]
+struct Keys {
+    action_buffer: String,
+    command_line: CommandLine,
+    keymap: Keymap,
+    /// Keys that start a binding in the keymap, waiting for the rest of it
+    binding: Vec<KeyEvent>,
+    macros: Macros,
+    /// Keys to handle before anything typed next, from macros that are playing
+    /// or bindings that were only partly typed
+    queued: VecDeque<KeyEvent>,
+    /// How many keys macros have played since a key was last typed
+    played: usize,
+}
+
+impl Keys {
+    fn new(keymap: Keymap) -> Self {
+        Self {
+            keymap,
+            ..Default::default()
+        }
+    }
+
+    /// A key the user typed, which goes into the macro being recorded unless
+    /// it's the `q` that stops recording
+    fn typed<T: SadieContext>(&mut self, context: &mut T, key: KeyEvent) -> Result<(), SadieError> {
+        let idle = self.action_buffer.is_empty() && self.binding.is_empty();
+        let stops = match self.keymap.lookup(context.mode(), &[key]) {
+            Lookup::Bound(stands_for) => stands_for == "q",
+            Lookup::Prefix => false,
+            Lookup::Unbound => key == KeyEvent::from('q'),
+        };
+        if idle && stops && !self.command_line.is_open() && self.macros.recording().is_some() {
+            self.macros.stop();
+            context.show_recording(None);
+            return Ok(());
+        }
+        self.macros.push(key);
+        self.played = 0;
+        self.handle(context, key)
+    }
+
+    fn handle<T: SadieContext>(
+        &mut self,
+        context: &mut T,
+        key: KeyEvent,
+    ) -> Result<(), SadieError> {
+        if self.command_line.is_open() {
+            let entered = self.command_line.input(key);
+            context.show_command_line(Some(&self.command_line).filter(|l| l.is_open()));
+            return match entered {
+                Some(Ok(command)) => context.apply_actions(Action::Command(command)),
+                Some(Err(e)) => {
+                    self.fail(context, e);
+                    Ok(())
+                }
+                None => Ok(()),
+            };
+        }
+
+        if takes_argument(&self.action_buffer) {
+            self.action_buffer.push_str(&key.to_string());
+        } else {
+            self.binding.push(key);
+            match self.keymap.lookup(context.mode(), &self.binding) {
+                Lookup::Bound(stands_for) => {
+                    self.action_buffer.push_str(stands_for);
+                    self.binding.clear();
+                }
+                Lookup::Prefix => return Ok(()),
+                // only the first key is taken as typed, the rest may start another binding
+                Lookup::Unbound => {
+                    let first = self.binding.remove(0);
+                    self.action_buffer.push_str(&first.to_string());
+                    self.binding
+                        .drain(..)
+                        .rev()
+                        .for_each(|k| self.queued.push_front(k));
+                }
+            }
+        }
+
+        let Some(parsed) = parse_action(&self.action_buffer, context.mode()) else {
+            return Ok(());
+        };
+        let buffer = std::mem::take(&mut self.action_buffer);
+        match parsed {
+            Ok(Action::CommandLine) => {
+                self.command_line.open();
+                context.show_command_line(Some(&self.command_line));
+            }
+            Ok(Action::Record(register)) => {
+                self.macros.record(register);
+                context.show_recording(Some(register));
+            }
+            Ok(Action::PlayMacro { register, count }) => {
+                let limit = Macros::MAX_KEYS - self.played;
+                match self.macros.play(register, count, limit) {
+                    Some(keys) => {
+                        self.played += keys.len();
+                        // a macro played by another one goes before the rest of it
+                        keys.into_iter()
+                            .rev()
+                            .for_each(|k| self.queued.push_front(k));
+                    }
+                    None => {
+                        let span = 0..buffer.len();
+                        self.fail(context, ActionError::RunawayMacro { buffer, span });
+                    }
+                }
+            }
+            Ok(a) => context.apply_actions(a)?,
+            Err(e) => self.fail(context, e),
+        }
+        Ok(())
+    }
+
+    /// Reports keys that were thrown away. Like vim, this stops any macro playing.
+    fn fail<T: SadieContext>(&mut self, context: &mut T, error: ActionError) {
+        self.queued.clear();
+        context.report_error(error);
+    }
 }
 
-fn run<T: SadieContext>(mut context: T) -> Result<(), SadieError> {
-    let mut action_buffer = String::new();
+fn run<T: SadieContext>(context: &mut T, keymap: Keymap) -> Result<(), SadieError> {
+    let mut keys = Keys::new(keymap);
     while context.is_alive() {
         // Update logic here
-        if let Some(c) = context.check_input() {
-            action_buffer.push(c);
-            if let Some(Ok(a)) = parse_action(&action_buffer) {
-                context.apply_actions(a)?;
-                action_buffer.clear();
-            }
+        match context.check_input() {
+            Some(InputEvent::Key(key)) => keys.typed(context, key)?,
+            Some(InputEvent::Mouse(event)) => context.handle_mouse(event)?,
+            None => {}
+        }
+        // a macro's keys are all handled before drawing again
+        while let Some(key) = keys.queued.pop_front() {
+            keys.handle(context, key)?;
         }
 
         // drawing logic here
@@ -61,8 +272,64 @@ fn run<T: SadieContext>(mut context: T) -> Result<(), SadieError> {
     Ok(())
 }
 
+/// Reads the keymap given on the command line, or the one in the config
+/// directory if there is one
+fn load_keymap(path: Option<PathBuf>) -> Result<Keymap, SadieError> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let config = env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+            match config.map(|c| c.join("sadie/keymap.toml")) {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Keymap::default()),
+            }
+        }
+    };
+
+    let text = fs::read_to_string(&path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => SadieError::CantFindFile {
+            path: path.display().to_string(),
+        },
+        _ => SadieError::Io(e),
+    })?;
+    Keymap::parse(&text).map_err(|error| SadieError::BadKeymap {
+        path: path.display().to_string(),
+        error,
+    })
+}
+
 fn main() -> Result<(), SadieError> {
     let args = Args::parse();
 
-    run(if args.tui { todo!() } else { RaylibContext::default() })
+    if let Some(keys) = args.script {
+        let canvas = CanvasBuilder::<_, ()>::init(Cp437)
+            .size((12, 8).into())
+            .build();
+        let mut context = HeadlessContext::new(canvas, parse_keys(&keys));
+        run(&mut context, load_keymap(args.keymap)?)?;
+
+        for e in context.errors() {
+            eprintln!("E: {e}");
+        }
+        for row in context.grid() {
+            println!("{}", row.into_iter().collect::<String>());
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = args.export {
+        let document = Document::load(&path)?;
+        let png = path.with_extension("png");
+        return export_document(&document, Some(&path), args.scale, &png);
+    }
+
+    // exporting types no keys, so a broken keymap doesn't get in its way
+    let keymap = load_keymap(args.keymap)?;
+    if args.tui {
+        run(&mut TerminalContext::new()?, keymap)
+    } else {
+        run(&mut RaylibContext::new(&args.font)?, keymap)
+    }
 }
@@ -0,0 +1,499 @@
+//! Terminal frontend, for editing where there is no window to open. Powered by crossterm
+
+use std::{
+    env,
+    io::{self, Stdout, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crossterm::{
+    cursor,
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers,
+    },
+    queue,
+    style::{self, Attribute, Colors, Print, SetAttribute, SetColors},
+    terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use raylib::prelude::Color;
+
+use crate::{
+    core::{
+        actions::{Action, ActionError, Mode},
+        canvas::{Canvas, CanvasBuilder},
+        command::Command,
+        command_line::CommandLine,
+        cp437::Cp437,
+        input::{InputEvent, Key, KeyEvent, Modifiers, MouseButton, MouseEvent, MouseEventKind},
+        layers::LayerCommand,
+        registers::Registers,
+        repeat::LastChange,
+        CanvasPos, CharID, Charset,
+    },
+    document::{Document, DocumentError},
+    gui::{export_document, CellColors, FontDescription},
+    SadieContext, SadieError,
+};
+
+/// How many colours the terminal can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorDepth {
+    TrueColor,
+    Ansi256,
+}
+
+impl ColorDepth {
+    /// Terminals that can show 24-bit colour advertise it through `COLORTERM`
+    fn detect() -> Self {
+        match env::var("COLORTERM").as_deref() {
+            Ok("truecolor" | "24bit") => Self::TrueColor,
+            _ => Self::Ansi256,
+        }
+    }
+
+    fn convert(self, c: Color) -> style::Color {
+        match self {
+            Self::TrueColor => style::Color::Rgb {
+                r: c.r,
+                g: c.g,
+                b: c.b,
+            },
+            Self::Ansi256 => style::Color::AnsiValue(to_ansi256(c)),
+        }
+    }
+}
+
+/// Picks the closest colour out of the 6x6x6 cube and grayscale ramp of the
+/// xterm 256 colour palette
+fn to_ansi256(c: Color) -> u8 {
+    if c.r == c.g && c.g == c.b {
+        return match c.r {
+            0..8 => 16,
+            249.. => 231,
+            v => 232 + ((v as u16 - 8) * 24 / 241) as u8,
+        };
+    }
+
+    let level = |v: u8| ((v as u16 * 5 + 127) / 255) as u8;
+    16 + 36 * level(c.r) + 6 * level(c.g) + level(c.b)
+}
+
+fn to_key_event(key: event::KeyEvent) -> Option<KeyEvent> {
+    let code = match key.code {
+        KeyCode::Char(c) => Key::Char(c),
+        KeyCode::Esc => Key::Escape,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::BackTab => {
+            let shift = Modifiers {
+                shift: true,
+                ..Modifiers::NONE
+            };
+            return Some(KeyEvent::new(Key::Tab, shift));
+        }
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        _ => return None,
+    };
+    let modifiers = Modifiers {
+        ctrl: key.modifiers.contains(KeyModifiers::CONTROL),
+        alt: key.modifiers.contains(KeyModifiers::ALT),
+        shift: key.modifiers.contains(KeyModifiers::SHIFT),
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+fn to_mouse_event(mouse: event::MouseEvent) -> Option<MouseEvent> {
+    let button = |b| match b {
+        event::MouseButton::Left => MouseButton::Left,
+        event::MouseButton::Right => MouseButton::Right,
+        event::MouseButton::Middle => MouseButton::Middle,
+    };
+    let kind = match mouse.kind {
+        event::MouseEventKind::Down(b) => MouseEventKind::Press(button(b)),
+        event::MouseEventKind::Drag(b) => MouseEventKind::Drag(button(b)),
+        event::MouseEventKind::Up(b) => MouseEventKind::Release(button(b)),
+        event::MouseEventKind::ScrollUp => MouseEventKind::Scroll(1),
+        event::MouseEventKind::ScrollDown => MouseEventKind::Scroll(-1),
+        _ => return None,
+    };
+    Some(MouseEvent {
+        kind,
+        position: (mouse.column as i32, mouse.row as i32).into(),
+    })
+}
+
+pub struct TerminalContext {
+    out: Stdout,
+    canvas: Canvas<Cp437, CellColors>,
+    depth: ColorDepth,
+    alive: bool,
+    /// Whether the screen is out of date
+    dirty: bool,
+    /// Shown on the bottom row of the terminal
+    status: String,
+    /// The command line being typed and where its cursor is, shown instead of
+    /// the status
+    prompt: Option<(String, usize)>,
+    /// The register a macro is being recorded into
+    recording: Option<char>,
+    /// Where `:w` saves to when it isn't given a file
+    file: Option<PathBuf>,
+    /// Kept from the opened file, so saving here doesn't lose what the
+    /// window frontend needs
+    font: Option<FontDescription>,
+    palette: Vec<Color>,
+    /// Yanked cells, kept when another file is opened
+    registers: Registers<CellColors>,
+    /// What painting stamps, the terminal has no pickers to choose another
+    brush: (CharID, CellColors),
+    /// What `.` makes again
+    last_change: LastChange<()>,
+}
+
+impl TerminalContext {
+    pub fn new() -> Result<Self, SadieError> {
+        let canvas = CanvasBuilder::init(Cp437)
+            .cursor_position(0, 0)
+            .size((12, 8).into())
+            .build();
+
+        terminal::enable_raw_mode().map_err(SadieError::Io)?;
+        let mut out = io::stdout();
+        queue!(out, EnterAlternateScreen, EnableMouseCapture, cursor::Hide)
+            .map_err(SadieError::Io)?;
+
+        Ok(Self {
+            out,
+            canvas,
+            depth: ColorDepth::detect(),
+            alive: true,
+            dirty: true,
+            status: String::new(),
+            prompt: None,
+            recording: None,
+            file: None,
+            font: None,
+            palette: vec![],
+            registers: Registers::default(),
+            // a full block, which shows off its colour
+            brush: (0xdb, CellColors::default()),
+            last_change: LastChange::default(),
+        })
+    }
+
+    fn write(&mut self, path: Option<PathBuf>) -> Result<(), SadieError> {
+        let path = path
+            .or_else(|| self.file.clone())
+            .ok_or(SadieError::NoFileName)?;
+        let document = Document {
+            font: self.font.clone(),
+            palette: self.palette.clone(),
+            ..Document::from_canvas(&self.canvas)
+        };
+        document.save(&path)?;
+        self.status = format!("\"{}\" written", path.display());
+        self.file = Some(path);
+        Ok(())
+    }
+
+    /// Draws with the font of the file that was opened, the terminal has none of its own
+    fn export(&mut self, path: &Path, scale: u16) -> Result<(), SadieError> {
+        let document = Document {
+            font: self.font.clone(),
+            ..Document::from_canvas(&self.canvas)
+        };
+        export_document(&document, self.file.as_deref(), scale, path)?;
+        self.status = format!("\"{}\" exported", path.display());
+        Ok(())
+    }
+
+    fn edit(&mut self, path: PathBuf) -> Result<(), SadieError> {
+        let document = Document::load(&path)?;
+        let bad = |error: DocumentError| SadieError::BadDocument {
+            path: path.display().to_string(),
+            error,
+        };
+        self.canvas = document.to_canvas(Cp437).map_err(bad)?;
+        self.font = document.font;
+        self.palette = document.palette;
+        self.file = Some(path);
+        Ok(())
+    }
+
+    /// Changes the layers, then says which one edits go to now
+    fn layer(&mut self, command: LayerCommand) -> Result<(), SadieError> {
+        self.canvas
+            .apply_layer(command)
+            .map_err(SadieError::Layer)?;
+        let layers = self.canvas.layers();
+        let active = self.canvas.active_layer();
+        self.status = format!(
+            "layer {} of {}: {}",
+            active + 1,
+            layers.len(),
+            layers[active].name
+        );
+        Ok(())
+    }
+
+    /// Applies an action to the canvas, remembering it for `.` if it's a change
+    fn change(&mut self, action: Action) {
+        self.last_change
+            .record(&action, self.canvas.selection_offset(), ());
+        if action.needs_brush() {
+            self.canvas
+                .apply_brush(action, self.brush, CellColors::matches);
+        } else {
+            self.canvas.apply_with(action, &mut self.registers);
+        }
+    }
+
+    fn render(&mut self) -> io::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        queue!(self.out, terminal::Clear(ClearType::All))?;
+
+        let cursor = self.canvas.active_cursor().map(|c| c.position());
+        let selection = self.canvas.active_cursor().and_then(|c| c.selection());
+        let size = self.canvas.size();
+        // leave the last row for the status line
+        for y in 0..size.height.min(rows.saturating_sub(1)) {
+            queue!(self.out, cursor::MoveTo(0, y))?;
+            for x in 0..size.width.min(columns) {
+                let (id, colors) = self.canvas.composite(x, y);
+                let glyph = self.canvas.charset().get_char(id);
+                let colors =
+                    Colors::new(self.depth.convert(colors.fg), self.depth.convert(colors.bg));
+                queue!(self.out, SetColors(colors))?;
+                let selected = selection.is_some_and(|s| s.contains((x, y).into()));
+                if selected || cursor == Some((x, y).into()) {
+                    queue!(
+                        self.out,
+                        SetAttribute(Attribute::Reverse),
+                        Print(glyph),
+                        SetAttribute(Attribute::NoReverse)
+                    )?;
+                } else {
+                    queue!(self.out, Print(glyph))?;
+                }
+            }
+        }
+
+        let status = match &self.prompt {
+            Some((line, _)) => format!(":{line}"),
+            None if self.status.is_empty() => self.canvas.mode().status(self.recording),
+            None => self.status.clone(),
+        };
+        queue!(
+            self.out,
+            style::ResetColor,
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            Print(status)
+        )?;
+        // the terminal's own cursor shows where typing goes on the command line
+        match &self.prompt {
+            Some((_, at)) => queue!(
+                self.out,
+                cursor::MoveTo(1 + *at as u16, rows.saturating_sub(1)),
+                cursor::Show
+            )?,
+            None => queue!(self.out, cursor::Hide)?,
+        }
+        self.out.flush()
+    }
+}
+
+impl Drop for TerminalContext {
+    fn drop(&mut self) {
+        // nothing else can be done if the terminal can't be restored
+        let _ = queue!(
+            self.out,
+            style::ResetColor,
+            cursor::Show,
+            DisableMouseCapture,
+            LeaveAlternateScreen
+        );
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl SadieContext for TerminalContext {
+    fn check_input(&mut self) -> Option<InputEvent> {
+        // waiting a frame for input keeps the loop from spinning
+        if !event::poll(Duration::from_millis(16)).unwrap_or(false) {
+            return None;
+        }
+
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => {
+                if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+                    self.alive = false;
+                    return None;
+                }
+                to_key_event(key).map(InputEvent::Key)
+            }
+            Ok(Event::Mouse(mouse)) => to_mouse_event(mouse).map(InputEvent::Mouse),
+            Ok(Event::Resize(..)) => {
+                self.dirty = true;
+                None
+            }
+            Ok(_) => None,
+            Err(_) => {
+                self.alive = false;
+                None
+            }
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.alive
+    }
+
+    fn mode(&self) -> Mode {
+        self.canvas.mode()
+    }
+
+    fn draw(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        self.dirty = false;
+        if self.render().is_err() {
+            // the terminal went away, like a dropped ssh connection
+            self.alive = false;
+        }
+    }
+
+    /// Clicking or dragging moves the cursor, since cells and the terminal's
+    /// characters line up
+    fn handle_mouse(&mut self, event: MouseEvent) -> Result<(), SadieError> {
+        let size = self.canvas.size();
+        let (x, y) = (event.position.x, event.position.y);
+        let on_canvas = (0..size.width as i32).contains(&x) && (0..size.height as i32).contains(&y);
+        match event.kind {
+            MouseEventKind::Press(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left)
+                if on_canvas =>
+            {
+                self.canvas
+                    .place_cursor(0, CanvasPos::new(x as u16, y as u16));
+            }
+            MouseEventKind::Scroll(lines) => {
+                self.canvas.apply(Action::MoveCursor((0, -lines).into()))
+            }
+            _ => return Ok(()),
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn apply_actions(&mut self, action: Action) -> Result<(), SadieError> {
+        self.status.clear();
+        self.dirty = true;
+        match action {
+            Action::Command(command) => {
+                let result = match command {
+                    Command::Write(path) => self.write(path),
+                    Command::Edit(path) => self.edit(path),
+                    Command::Export { path, scale } => self.export(&path, scale),
+                    Command::Resize { size, anchor } => {
+                        self.canvas.resize(size, anchor, (0, CellColors::default()));
+                        Ok(())
+                    }
+                    Command::Layer(command) => self.layer(command),
+                };
+                if let Err(e) = result {
+                    self.status = format!("E: {e}");
+                }
+            }
+            Action::Repeat(count) => {
+                if let Some(change) = self.last_change.replay(count) {
+                    if let Some(offset) = change.selection {
+                        self.canvas.reselect(offset);
+                    }
+                    self.change(change.action);
+                }
+            }
+            action => self.change(action),
+        }
+        Ok(())
+    }
+
+    fn report_error(&mut self, error: ActionError) {
+        self.status = format!("E: {error}");
+        self.dirty = true;
+    }
+
+    fn show_command_line(&mut self, line: Option<&CommandLine>) {
+        self.prompt = line.map(|l| (l.text().to_string(), l.cursor()));
+        self.dirty = true;
+    }
+
+    fn show_recording(&mut self, register: Option<char>) {
+        self.recording = register;
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tui_test {
+    use super::*;
+
+    #[test]
+    fn approximating_colors() {
+        assert_eq!(to_ansi256(Color::BLACK), 16);
+        assert_eq!(to_ansi256(Color::WHITE), 231);
+        assert_eq!(to_ansi256(Color::new(255, 0, 0, 255)), 196);
+        assert_eq!(to_ansi256(Color::new(128, 128, 128, 255)), 243);
+        assert_eq!(to_ansi256(Color::new(0, 135, 81, 255)), 36);
+    }
+
+    #[test]
+    fn keys() {
+        let key = |code, modifiers| to_key_event(event::KeyEvent::new(code, modifiers));
+        assert_eq!(
+            key(KeyCode::Char('r'), KeyModifiers::CONTROL),
+            Some(KeyEvent::new(Key::Char('r'), Modifiers::CTRL))
+        );
+        assert_eq!(
+            key(KeyCode::Char('G'), KeyModifiers::SHIFT),
+            Some('G'.into())
+        );
+        assert_eq!(
+            key(KeyCode::Esc, KeyModifiers::NONE),
+            Some(Key::Escape.into())
+        );
+        assert_eq!(
+            key(KeyCode::BackTab, KeyModifiers::SHIFT).map(|k| k.to_string()),
+            Some("<S-Tab>".into())
+        );
+        assert_eq!(key(KeyCode::F(1), KeyModifiers::NONE), None);
+    }
+
+    #[test]
+    fn mouse_events() {
+        let mouse = |kind| event::MouseEvent {
+            kind,
+            column: 3,
+            row: 2,
+            modifiers: KeyModifiers::NONE,
+        };
+        assert_eq!(
+            to_mouse_event(mouse(event::MouseEventKind::Drag(event::MouseButton::Left))),
+            Some(MouseEvent {
+                kind: MouseEventKind::Drag(MouseButton::Left),
+                position: (3, 2).into()
+            })
+        );
+        assert_eq!(
+            to_mouse_event(mouse(event::MouseEventKind::ScrollDown)).map(|m| m.kind),
+            Some(MouseEventKind::Scroll(-1))
+        );
+        assert_eq!(to_mouse_event(mouse(event::MouseEventKind::Moved)), None);
+    }
+}